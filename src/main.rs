@@ -1,10 +1,21 @@
-use std::{error::Error, path::Path};
+use chrono::Local;
+use std::{
+    error::Error,
+    fs,
+    path::{Path, PathBuf},
+};
 
 mod args {
+    use chrono::format::{Item, StrftimeItems};
+    use chrono::{DateTime, Local, NaiveDate, NaiveDateTime, TimeZone};
     use std::error::Error;
     use std::str::FromStr;
+    use std::time::Duration;
+    use structopt::clap::{self, ArgMatches};
     use structopt::StructOpt;
 
+    use crate::terminal;
+
     #[derive(StructOpt, Debug)]
     #[structopt(
         name = "ls",
@@ -34,6 +45,29 @@ mod args {
         )]
         pub escape: bool,
 
+        #[structopt(
+            long = "escape-style",
+            value_name = "STYLE",
+            help = "Escape nongraphic bytes as octal (\\NNN, the default) or hex (\\xNN); implies -b",
+            possible_values = &["octal", "hex"]
+        )]
+        pub escape_style: Option<EscapeStyle>,
+
+        #[structopt(
+            short = "q",
+            long = "hide-control-chars",
+            overrides_with = "show-control-chars",
+            help = "Print ? instead of nongraphic characters (default when writing to a terminal)"
+        )]
+        pub hide_control_chars: bool,
+
+        #[structopt(
+            long = "show-control-chars",
+            overrides_with = "hide-control-chars",
+            help = "Print nongraphic characters as-is (default when not writing to a terminal)"
+        )]
+        pub show_control_chars: bool,
+
         #[structopt(
             name("time"),
             value_names(&["WHEN"]),
@@ -51,13 +85,35 @@ mod args {
         )]
         pub classify: bool,
 
+        #[structopt(
+            short = "p",
+            help = "Append / to directory names; same as --indicator-style=slash"
+        )]
+        pub slash: bool,
+
+        #[structopt(
+            long = "indicator-style",
+            value_name = "WORD",
+            help = "Append indicators to names: none, slash (-p) or classify (-F). When several \
+                    are given, the one with the most indicators wins",
+            possible_values = &["none", "slash", "classify"]
+        )]
+        pub indicator_style: Option<IndicatorStyle>,
+
         #[structopt(
             short = "d",
             long = "max-depth",
-            help = "Limit the components of the path"
+            help = "Show entries at most N levels below the listing root (its direct entries are level 1; 0 lists the root itself)"
         )]
         pub max_depth: Option<usize>,
 
+        #[structopt(
+            long = "min-depth",
+            value_name = "N",
+            help = "Show entries at least N levels below the listing root (its direct entries are level 1)"
+        )]
+        pub min_depth: Option<usize>,
+
         #[structopt(
             short = "l",
             long = "limit",
@@ -65,8 +121,601 @@ mod args {
         )]
         pub limit: Option<usize>,
 
-        #[structopt(name = "path", help = "The path to list", index = 1)]
-        pub path: Option<String>,
+        #[structopt(
+            long = "max-total-entries",
+            value_name = "N",
+            help = "Stop after N entries in total across every directory listed (a safety cap for \
+                    -R on huge trees); reports the truncation on stderr and exits with status 4"
+        )]
+        pub max_total_entries: Option<usize>,
+
+        #[structopt(
+            long = "size-by-type",
+            help = "Print the total size of directories, regular files and symlinks after the listing"
+        )]
+        pub size_by_type: bool,
+
+        #[structopt(
+            long = "time-relative",
+            help = "Show times relative to now (e.g. \"3 days ago\"); implies -c mtime when no time is given"
+        )]
+        pub time_relative: bool,
+
+        #[structopt(
+            long = "time-style",
+            value_name = "STYLE",
+            help = "Time format: full-iso, long-iso, iso, +FORMAT (strftime), or +OLD<newline>RECENT \
+                    to format times within the last six months with RECENT; implies -c mtime \
+                    when no time is given"
+        )]
+        pub time_style: Option<TimeStyle>,
+
+        #[structopt(
+            long = "relative-granularity",
+            value_name = "UNIT",
+            help = "Spell --time-relative times down to UNIT, e.g. hour gives \"3 days 4 hours ago\"; \
+                    by default only the coarsest unit is shown",
+            possible_values = &["year", "month", "week", "day", "hour", "minute", "second"]
+        )]
+        pub relative_granularity: Option<TimeUnit>,
+
+        #[structopt(
+            long = "at",
+            value_name = "DATETIME",
+            help = "Use DATETIME instead of the current time as \"now\" for --time-relative, \
+                    --time-style and --json-relative",
+            parse(try_from_str = parse_datetime)
+        )]
+        pub at: Option<DateTime<Local>>,
+
+        #[structopt(
+            long = "exec",
+            value_name = "CMD",
+            help = "Run CMD for each entry instead of printing it; `{}` is replaced by the path. \
+                    A trailing `+` runs CMD once with all paths. CMD is split on whitespace, not run through a shell"
+        )]
+        pub exec: Option<String>,
+
+        #[structopt(
+            long = "confirm",
+            requires = "exec",
+            help = "Ask for confirmation on stdin before each --exec invocation"
+        )]
+        pub confirm: bool,
+
+        #[structopt(
+            long = "hide-extension",
+            help = "Display file names without their extension (display only; sorting and paths are unaffected)"
+        )]
+        pub hide_extension: bool,
+
+        #[structopt(
+            long = "only-readable",
+            help = "Only show entries the current user can read"
+        )]
+        pub only_readable: bool,
+
+        #[structopt(
+            long = "only-writable",
+            help = "Only show entries the current user can write"
+        )]
+        pub only_writable: bool,
+
+        #[structopt(
+            long = "only-executable",
+            help = "Only show entries the current user can execute (or search, for directories)"
+        )]
+        pub only_executable: bool,
+
+        #[structopt(
+            long = "symlink-chain",
+            help = "Show the full resolution chain of each symlink (link -> a -> b -> target)"
+        )]
+        pub symlink_chain: bool,
+
+        #[structopt(
+            short = "s",
+            long = "size",
+            help = "Print the allocated size of each file, in 1K blocks, and a total line"
+        )]
+        pub size: bool,
+
+        #[structopt(
+            long = "total-position",
+            value_name = "WHERE",
+            help = "Print the -s 'total' line before (default, as GNU ls) or after the entries",
+            possible_values = &["before", "after"],
+            default_value = "before"
+        )]
+        pub total_position: TotalPosition,
+
+        #[structopt(
+            long = "modified-within",
+            value_name = "DURATION",
+            help = "Only show entries modified within DURATION (e.g. 30m, 2h, 3d, 1w)",
+            parse(try_from_str = parse_duration)
+        )]
+        pub modified_within: Option<Duration>,
+
+        #[structopt(
+            long = "modified-before",
+            value_name = "DURATION",
+            help = "Only show entries last modified more than DURATION ago (e.g. 30m, 2h, 3d, 1w)",
+            parse(try_from_str = parse_duration)
+        )]
+        pub modified_before: Option<Duration>,
+
+        #[structopt(
+            long = "larger-than",
+            value_name = "SIZE",
+            help = "Only show entries larger than SIZE bytes (e.g. 500K, 10M, 1GB)",
+            parse(try_from_str = crate::size::parse_size)
+        )]
+        pub larger_than: Option<u64>,
+
+        #[structopt(
+            long = "smaller-than",
+            value_name = "SIZE",
+            help = "Only show entries smaller than SIZE bytes (e.g. 500K, 10M, 1GB)",
+            parse(try_from_str = crate::size::parse_size)
+        )]
+        pub smaller_than: Option<u64>,
+
+        #[structopt(
+            long = "exclude",
+            value_name = "GLOB",
+            number_of_values = 1,
+            help = "Hide entries whose name matches GLOB (*, ? and [...]); repeatable; -R does not \
+                    descend into excluded directories"
+        )]
+        pub exclude: Vec<String>,
+
+        #[structopt(
+            long = "no-ignore",
+            help = "Do not read .ignore, .rgignore or .lsrignore files. Otherwise each one's glob \
+                    patterns (one per line, `!GLOB` to re-include) hide matching entries in its \
+                    directory and below, down from the listed directory (files above it do not \
+                    apply); nearer directories win, and within one directory .lsrignore beats \
+                    .rgignore beats .ignore"
+        )]
+        pub no_ignore: bool,
+
+        #[structopt(
+            long = "exclude-owner",
+            value_name = "USER",
+            number_of_values = 1,
+            help = "Hide entries owned by USER (a name or numeric uid); repeatable",
+            parse(try_from_str = parse_owner)
+        )]
+        pub exclude_owner: Vec<u32>,
+
+        #[structopt(
+            short = "1",
+            help = "List one file per line; same as --format=single-column"
+        )]
+        pub one_per_line: bool,
+
+        #[structopt(
+            long = "format",
+            value_name = "WORD",
+            help = "Output layout",
+            possible_values = &["single-column", "commas", "vertical", "across", "horizontal"]
+        )]
+        pub format: Option<Format>,
+
+        #[structopt(
+            short = "C",
+            help = "List entries in columns, filled top to bottom; same as --format=vertical \
+                    (the default on a terminal)"
+        )]
+        pub columns: bool,
+
+        #[structopt(
+            short = "x",
+            help = "List entries in columns, filled left to right; same as --format=across"
+        )]
+        pub across: bool,
+
+        #[structopt(
+            short = "m",
+            help = "Fill width with a comma separated list of entries"
+        )]
+        pub commas: bool,
+
+        #[structopt(
+            short = "w",
+            long = "width",
+            value_name = "COLS",
+            help = "Set output width to COLS; 0 means no limit"
+        )]
+        pub width: Option<usize>,
+
+        #[structopt(
+            long = "wrap-names",
+            help = "With one entry per line, wrap lines wider than the output width, continuing \
+                    each name on indented lines instead of leaving the terminal to break it"
+        )]
+        pub wrap_names: bool,
+
+        #[structopt(
+            long = "color",
+            value_name = "WHEN",
+            help = "Colorize names: always (default when WHEN is omitted), auto or never",
+            possible_values = &["always", "auto", "never"],
+            require_equals = true
+        )]
+        pub color: Option<Option<ColorWhen>>,
+
+        #[structopt(
+            long = "split-output",
+            value_name = "DIR",
+            requires = "recursive",
+            help = "With -R, write each directory's listing to its own file instead of stdout: \
+                    DIR mirrors the tree, with a _listing.txt in each directory"
+        )]
+        pub split_output: Option<String>,
+
+        #[structopt(
+            long = "git-modified",
+            help = "Only show files git reports as modified, staged or untracked, and the \
+                    directories leading to them; fails outside a git repository"
+        )]
+        pub git_modified: bool,
+
+        #[structopt(
+            long = "fail-on-empty",
+            help = "Exit with status 3 when no entries are listed after filtering"
+        )]
+        pub fail_on_empty: bool,
+
+        #[structopt(
+            long = "align-ext",
+            help = "Pad names so their extensions line up in a column, e.g. 'report   .pdf'; \
+                    names without one (and dotfiles) are left as they are"
+        )]
+        pub align_ext: bool,
+
+        #[structopt(
+            long = "mark-hardlinks",
+            help = "Tag files that share an inode with [#N], the same N for every link to it \
+                    within a directory's listing (no-op outside Unix)"
+        )]
+        pub mark_hardlinks: bool,
+
+        #[structopt(
+            long = "number",
+            value_name = "SCOPE",
+            help = "Prefix each entry with its index, counting across the whole listing \
+                    (global, the default when SCOPE is omitted) or restarting in each -R \
+                    directory (per-dir)",
+            possible_values = &["global", "per-dir"],
+            require_equals = true
+        )]
+        pub number: Option<Option<Numbering>>,
+
+        #[structopt(
+            long = "color-by",
+            value_name = "SCHEME",
+            help = "What colors encode: type (default), owner or age",
+            possible_values = &["type", "owner", "age"],
+            default_value = "type"
+        )]
+        pub color_by: ColorBy,
+
+        #[structopt(
+            long = "theme",
+            value_name = "NAME",
+            help = "Palette for --color-by=type; the default one honors LS_COLORS",
+            possible_values = &["default", "dark", "light", "solarized"],
+            default_value = "default"
+        )]
+        pub theme: ThemeName,
+
+        #[structopt(
+            long = "theme-file",
+            value_name = "FILE",
+            help = "Override --theme colors with KEY=SGR pairs from FILE, one per line or \
+                    colon-separated. Keys: di, ln, or, pi, so, bd, cd, ex, ow, tw, st, *.EXT"
+        )]
+        pub theme_file: Option<String>,
+
+        #[structopt(
+            long = "smart-exec-color",
+            help = "Only color files with execute bits as executable if they start with #! or an \
+                    ELF, PE or Mach-O header"
+        )]
+        pub smart_exec_color: bool,
+
+        #[structopt(
+            long = "count-links",
+            help = "Count the size of hard-linked files once per link in size summaries instead of once per inode"
+        )]
+        pub count_links: bool,
+
+        #[structopt(short = "S", help = "Sort by file size, largest first")]
+        pub sort_size: bool,
+
+        #[structopt(short = "t", help = "Sort by time, newest first; see -c")]
+        pub sort_time: bool,
+
+        #[structopt(short = "X", help = "Sort alphabetically by extension")]
+        pub sort_extension: bool,
+
+        #[structopt(short = "v", help = "Natural sort of (version) numbers within names")]
+        pub sort_version: bool,
+
+        #[structopt(short = "U", help = "Do not sort; list entries in directory order")]
+        pub sort_none: bool,
+
+        #[structopt(
+            long = "sort",
+            value_name = "WORD",
+            help = "Sort by WORD instead of name",
+            possible_values = &[
+                "none",
+                "name",
+                "size",
+                "time",
+                "extension",
+                "version",
+                "links",
+                "child-count"
+            ]
+        )]
+        pub sort: Option<SortField>,
+
+        #[structopt(short = "u", help = "Use access time; same as -c atime")]
+        pub access_time: bool,
+
+        #[structopt(short = "r", long = "reverse", help = "Reverse order while sorting")]
+        pub reverse: bool,
+
+        /// Sort key after applying the precedence rules in `resolve_sort`.
+        #[structopt(skip)]
+        pub sort_field: SortField,
+
+        #[structopt(
+            short = "R",
+            long = "recursive",
+            help = "List subdirectories recursively"
+        )]
+        pub recursive: bool,
+
+        #[structopt(
+            long = "stat",
+            help = "Print a detailed, stat-like view of the path itself instead of listing it"
+        )]
+        pub stat: bool,
+
+        #[structopt(
+            long = "parallel",
+            help = "With -R, read directories on multiple threads; output order is unchanged"
+        )]
+        pub parallel: bool,
+
+        #[structopt(
+            short = "h",
+            long = "human-readable",
+            help = "Print sizes like 1K 234M 2G"
+        )]
+        pub human_readable: bool,
+
+        #[structopt(
+            long = "json",
+            help = "Print entries as a JSON array of objects (name, path, type, size, modified, accessed)"
+        )]
+        pub json: bool,
+
+        #[structopt(
+            long = "json-relative",
+            requires = "json",
+            help = "Add modified_relative and accessed_relative fields (e.g. \"3 days ago\") to --json \
+                    output, measured from --at or now"
+        )]
+        pub json_relative: bool,
+
+        #[structopt(
+            long = "json-schema",
+            help = "Print the JSON Schema describing one --json entry object, then exit"
+        )]
+        pub json_schema: bool,
+
+        #[structopt(
+            long = "file-size",
+            help = "Print each entry's size in bytes, right-aligned before its name (with -h, like 4.0K)"
+        )]
+        pub file_size: bool,
+
+        #[structopt(
+            long = "percent",
+            help = "Append each entry's share of the listing's total size, like (12.3%); directories \
+                    count their own entry size"
+        )]
+        pub percent: bool,
+
+        #[structopt(
+            long = "device-width",
+            value_name = "N",
+            help = "Pad the --device-id column to N characters instead of its widest value"
+        )]
+        pub device_width: Option<usize>,
+
+        #[structopt(
+            long = "blocks-width",
+            value_name = "N",
+            help = "Pad the -s column to N characters instead of its widest value"
+        )]
+        pub blocks_width: Option<usize>,
+
+        #[structopt(
+            long = "size-width",
+            value_name = "N",
+            help = "Pad the --file-size column to N characters instead of its widest value"
+        )]
+        pub size_width: Option<usize>,
+
+        #[structopt(
+            long = "truncate-columns",
+            help = "Cut values wider than a fixed column width, ending them with '…'; by default \
+                    they overflow"
+        )]
+        pub truncate_columns: bool,
+
+        #[structopt(
+            long = "no-self-indicator",
+            help = "With --max-depth=0, do not append the -F indicator to the listed directory itself"
+        )]
+        pub no_self_indicator: bool,
+
+        #[structopt(
+            long = "low-memory",
+            help = "Hold at most one directory's entries at a time; disables --parallel, \
+                    whose read-ahead keeps the whole tree in memory"
+        )]
+        pub low_memory: bool,
+
+        #[structopt(
+            long = "rollup",
+            help = "Instead of listing, print each directory's total size beneath it, largest \
+                    first, like du; --max-depth limits which directories are reported"
+        )]
+        pub rollup: bool,
+
+        #[structopt(
+            long = "rollup-follow-symlinks",
+            requires = "rollup",
+            help = "Make --rollup count what symlinks point to, descending into linked directories \
+                    (each directory once), instead of the links themselves"
+        )]
+        pub rollup_follow_symlinks: bool,
+
+        #[structopt(
+            long = "audit-perms",
+            conflicts_with_all = &["json", "exec"],
+            help = "Instead of listing, report world-writable files, setuid/setgid files and \
+                    group-writable files owned by someone else; with -R, audits the whole tree. \
+                    Exits 1 if a world-writable setuid file is found"
+        )]
+        pub audit_perms: bool,
+
+        #[structopt(
+            long = "prune-empty",
+            requires = "recursive",
+            conflicts_with = "low-memory",
+            help = "With -R, hide directories with no matching entries anywhere beneath them; \
+                    filters then apply to files only. Needs the whole tree in memory, so it \
+                    cannot be combined with --low-memory"
+        )]
+        pub prune_empty: bool,
+
+        #[structopt(
+            short = "L",
+            long = "dereference",
+            help = "Show information for the target of each symbolic link (type, -F indicator, \
+                    color, sizes, times) instead of the link itself"
+        )]
+        pub dereference: bool,
+
+        #[structopt(
+            long = "symlinks-only",
+            help = "Only show symbolic links; -R still descends into every directory"
+        )]
+        pub symlinks_only: bool,
+
+        #[structopt(
+            long = "broken-symlinks",
+            help = "Only show symbolic links whose target does not exist"
+        )]
+        pub broken_symlinks: bool,
+
+        #[structopt(
+            long = "device-id",
+            help = "Print the device ID (major:minor on Linux) of the filesystem holding each entry"
+        )]
+        pub device_id: bool,
+
+        #[structopt(
+            long = "newest",
+            conflicts_with = "oldest",
+            help = "Print only the most recently modified entry that passes the filters"
+        )]
+        pub newest: bool,
+
+        #[structopt(
+            long = "oldest",
+            help = "Print only the least recently modified entry that passes the filters"
+        )]
+        pub oldest: bool,
+
+        #[structopt(
+            long = "zero",
+            help = "End each record with NUL instead of newline. Records with extra fields \
+                    (-s, --device-id, a time column) have their fields NUL-separated, unpadded, \
+                    and end with a double NUL"
+        )]
+        pub zero: bool,
+
+        #[structopt(
+            long = "paths-only",
+            conflicts_with_all = &["json", "exec", "split-output"],
+            help = "Print only each entry's path as reached from the listed directory, one per line \
+                    (NUL-terminated with --zero), with no headers, columns, indicators or color; \
+                    sorting and filters still apply"
+        )]
+        pub paths_only: bool,
+
+        #[structopt(
+            long = "files-from",
+            value_name = "FILE",
+            conflicts_with = "path",
+            help = "List exactly the paths named in FILE (one per line, or NUL-separated), \
+                    sorted and filtered as usual and shown with their directory"
+        )]
+        pub files_from: Option<String>,
+
+        #[structopt(
+            long = "mark-mounts",
+            help = "Append [mount] to directories on a different filesystem than their parent (Unix only)"
+        )]
+        pub mark_mounts: bool,
+
+        #[structopt(
+            long = "sanitize-names",
+            overrides_with = "raw-names",
+            help = "Show terminal escape sequences in names as text, e.g. \\e[31m (default on a terminal)"
+        )]
+        pub sanitize_names: bool,
+
+        #[structopt(
+            long = "raw-names",
+            overrides_with = "sanitize-names",
+            help = "Let terminal escape sequences in names through unchanged"
+        )]
+        pub raw_names: bool,
+
+        #[structopt(
+            long = "unescape",
+            help = "Instead of listing, read names escaped by -b from stdin, one per line (NUL \
+                    with --zero), and print them decoded to their raw bytes"
+        )]
+        pub unescape: bool,
+
+        /// Development aid: report names whose -b escaping does not decode
+        /// back to the original bytes.
+        #[structopt(long = "verify-quoting", hidden = true)]
+        pub verify_quoting: bool,
+
+        /// Troubleshooting aid: print the resolved arguments and the
+        /// decisions derived from them to stderr, then list as usual.
+        #[structopt(long = "debug-args", hidden = true)]
+        pub debug_args: bool,
+
+        #[structopt(
+            name = "path",
+            help = "The paths to list (default: the current directory)",
+            index = 1
+        )]
+        pub paths: Vec<String>,
     }
 
     #[derive(Clone, Copy, Debug, PartialEq)]
@@ -89,139 +738,4665 @@ mod args {
         }
     }
 
-    pub fn parse_args() -> Result<Arguments, Box<dyn Error>> {
-        Ok(Arguments::from_args())
-    }
-}
+    /// Accepts RFC 3339 (`2023-01-31T12:00:00+02:00`) or a local
+    /// `YYYY-MM-DD[ HH:MM[:SS]]` date-time.
+    fn parse_datetime(s: &str) -> Result<DateTime<Local>, String> {
+        if let Ok(datetime) = DateTime::parse_from_rfc3339(s) {
+            return Ok(datetime.with_timezone(&Local));
+        }
+
+        let naive = ["%Y-%m-%d %H:%M:%S", "%Y-%m-%dT%H:%M:%S", "%Y-%m-%d %H:%M"]
+            .iter()
+            .find_map(|format| NaiveDateTime::parse_from_str(s, format).ok())
+            .or_else(|| {
+                NaiveDate::parse_from_str(s, "%Y-%m-%d")
+                    .ok()
+                    .and_then(|date| date.and_hms_opt(0, 0, 0))
+            })
+            .ok_or_else(|| {
+                format!(
+                    "invalid date-time '{}': expected RFC 3339 or 'YYYY-MM-DD[ HH:MM[:SS]]'",
+                    s
+                )
+            })?;
+
+        Local
+            .from_local_datetime(&naive)
+            .earliest()
+            .ok_or_else(|| format!("invalid local date-time '{}'", s))
+    }
+
+    /// Resolves a user name or numeric uid.
+    #[cfg(unix)]
+    pub fn parse_owner(s: &str) -> Result<u32, String> {
+        if let Ok(uid) = s.parse() {
+            return Ok(uid);
+        }
+        let name = std::ffi::CString::new(s).map_err(|_| format!("invalid user '{}'", s))?;
+        // SAFETY: `name` is NUL-terminated; the returned record is only read
+        // before any other passwd call.
+        let passwd = unsafe { libc::getpwnam(name.as_ptr()) };
+        if passwd.is_null() {
+            Err(format!("invalid user '{}'", s))
+        } else {
+            Ok(unsafe { (*passwd).pw_uid })
+        }
+    }
+
+    #[cfg(not(unix))]
+    pub fn parse_owner(s: &str) -> Result<u32, String> {
+        Err(format!("cannot filter by owner '{}' on this platform", s))
+    }
+
+    /// Parses a human duration such as `90s`, `30m`, `2h`, `3d` or `1w`.
+    pub fn parse_duration(s: &str) -> Result<Duration, String> {
+        let error = || {
+            format!(
+                "invalid duration '{}': expected a number followed by one of s, m, h, d, w",
+                s
+            )
+        };
+
+        let split = s.find(|c: char| !c.is_ascii_digit()).ok_or_else(error)?;
+        let (count, unit) = s.split_at(split);
+        let count: u64 = count.parse().map_err(|_| error())?;
+        let seconds = match unit {
+            "s" => 1,
+            "m" => 60,
+            "h" => 60 * 60,
+            "d" => 24 * 60 * 60,
+            "w" => 7 * 24 * 60 * 60,
+            _ => return Err(error()),
+        };
+
+        count
+            .checked_mul(seconds)
+            .map(Duration::from_secs)
+            .ok_or_else(error)
+    }
+
+    #[derive(Clone, Copy, Debug, PartialEq)]
+    pub enum Format {
+        SingleColumn,
+        Commas,
+        /// Grid filled column by column (`-C`), as GNU ls does by default.
+        Vertical,
+        /// Grid filled row by row (`-x`).
+        Across,
+    }
+
+    impl FromStr for Format {
+        type Err = Box<dyn Error>;
+
+        fn from_str(s: &str) -> Result<Self, Self::Err> {
+            match s {
+                "single-column" => Ok(Format::SingleColumn),
+                "commas" => Ok(Format::Commas),
+                "vertical" => Ok(Format::Vertical),
+                "across" | "horizontal" => Ok(Format::Across),
+                _ => Err(format!("invalid argument '{}' for '--format'", s).into()),
+            }
+        }
+    }
+
+    #[derive(Clone, Copy, Debug, PartialEq)]
+    pub enum ColorWhen {
+        Always,
+        Auto,
+        Never,
+    }
+
+    impl FromStr for ColorWhen {
+        type Err = Box<dyn Error>;
+
+        fn from_str(s: &str) -> Result<Self, Self::Err> {
+            match s {
+                "always" => Ok(ColorWhen::Always),
+                "auto" => Ok(ColorWhen::Auto),
+                "never" => Ok(ColorWhen::Never),
+                _ => Err(format!("invalid argument '{}' for '--color'", s).into()),
+            }
+        }
+    }
+
+    #[derive(Clone, Copy, Debug, PartialEq)]
+    pub enum ColorBy {
+        Type,
+        Owner,
+        Age,
+    }
+
+    impl FromStr for ColorBy {
+        type Err = Box<dyn Error>;
+
+        fn from_str(s: &str) -> Result<Self, Self::Err> {
+            match s {
+                "type" => Ok(ColorBy::Type),
+                "owner" => Ok(ColorBy::Owner),
+                "age" => Ok(ColorBy::Age),
+                _ => Err(format!("invalid argument '{}' for '--color-by'", s).into()),
+            }
+        }
+    }
+
+    /// Calendar units for relative times, coarsest first.
+    #[derive(Clone, Copy, Debug, PartialEq, PartialOrd)]
+    pub enum TimeUnit {
+        Year,
+        Month,
+        Week,
+        Day,
+        Hour,
+        Minute,
+        Second,
+    }
+
+    impl TimeUnit {
+        pub const ALL: [TimeUnit; 7] = [
+            TimeUnit::Year,
+            TimeUnit::Month,
+            TimeUnit::Week,
+            TimeUnit::Day,
+            TimeUnit::Hour,
+            TimeUnit::Minute,
+            TimeUnit::Second,
+        ];
+
+        pub fn name(self) -> &'static str {
+            match self {
+                TimeUnit::Year => "year",
+                TimeUnit::Month => "month",
+                TimeUnit::Week => "week",
+                TimeUnit::Day => "day",
+                TimeUnit::Hour => "hour",
+                TimeUnit::Minute => "minute",
+                TimeUnit::Second => "second",
+            }
+        }
+
+        /// Nominal length; months are 30 days and years 365.
+        pub fn seconds(self) -> i64 {
+            match self {
+                TimeUnit::Year => 365 * 24 * 60 * 60,
+                TimeUnit::Month => 30 * 24 * 60 * 60,
+                TimeUnit::Week => 7 * 24 * 60 * 60,
+                TimeUnit::Day => 24 * 60 * 60,
+                TimeUnit::Hour => 60 * 60,
+                TimeUnit::Minute => 60,
+                TimeUnit::Second => 1,
+            }
+        }
+    }
+
+    impl FromStr for TimeUnit {
+        type Err = Box<dyn Error>;
+
+        fn from_str(s: &str) -> Result<Self, Self::Err> {
+            TimeUnit::ALL
+                .into_iter()
+                .find(|unit| unit.name() == s)
+                .ok_or_else(|| {
+                    format!("invalid argument '{}' for '--relative-granularity'", s).into()
+                })
+        }
+    }
+
+    #[derive(Clone, Copy, Debug, PartialEq)]
+    pub enum EscapeStyle {
+        Octal,
+        Hex,
+    }
+
+    impl FromStr for EscapeStyle {
+        type Err = Box<dyn Error>;
+
+        fn from_str(s: &str) -> Result<Self, Self::Err> {
+            match s {
+                "octal" => Ok(EscapeStyle::Octal),
+                "hex" => Ok(EscapeStyle::Hex),
+                _ => Err(format!("invalid argument '{}' for '--escape-style'", s).into()),
+            }
+        }
+    }
+
+    /// `--time-style` formats: `old` for times more than six months before
+    /// "now" (or in the future), `recent` otherwise.
+    #[derive(Clone, Debug, PartialEq)]
+    pub struct TimeStyle {
+        pub old: String,
+        pub recent: String,
+    }
+
+    impl FromStr for TimeStyle {
+        type Err = Box<dyn Error>;
+
+        fn from_str(s: &str) -> Result<Self, Self::Err> {
+            let (old, recent) = match s {
+                "full-iso" => ("%Y-%m-%d %H:%M:%S.%f %z", None),
+                "long-iso" => ("%Y-%m-%d %H:%M", None),
+                "iso" => ("%Y-%m-%d ", Some("%m-%d %H:%M")),
+                _ => match s.strip_prefix('+') {
+                    Some(formats) => match formats.split_once('\n') {
+                        Some((old, recent)) => (old, Some(recent)),
+                        None => (formats, None),
+                    },
+                    None => {
+                        return Err(format!("invalid argument '{}' for '--time-style'", s).into())
+                    }
+                },
+            };
+            let recent = recent.unwrap_or(old);
+            for format in [old, recent] {
+                // chrono panics when formatting with a bad specifier.
+                if StrftimeItems::new(format).any(|item| item == Item::Error) {
+                    return Err(
+                        format!("invalid time format '{}' in '--time-style'", format).into(),
+                    );
+                }
+            }
+            Ok(TimeStyle {
+                old: old.to_string(),
+                recent: recent.to_string(),
+            })
+        }
+    }
+
+    /// Ordered from fewest to most indicators.
+    #[derive(Clone, Copy, Debug, PartialEq, PartialOrd)]
+    pub enum IndicatorStyle {
+        None,
+        Slash,
+        Classify,
+    }
+
+    impl FromStr for IndicatorStyle {
+        type Err = Box<dyn Error>;
+
+        fn from_str(s: &str) -> Result<Self, Self::Err> {
+            match s {
+                "none" => Ok(IndicatorStyle::None),
+                "slash" => Ok(IndicatorStyle::Slash),
+                "classify" => Ok(IndicatorStyle::Classify),
+                _ => Err(format!("invalid argument '{}' for '--indicator-style'", s).into()),
+            }
+        }
+    }
+
+    #[derive(Clone, Copy, Debug, PartialEq)]
+    pub enum Numbering {
+        Global,
+        PerDir,
+    }
+
+    impl FromStr for Numbering {
+        type Err = Box<dyn Error>;
+
+        fn from_str(s: &str) -> Result<Self, Self::Err> {
+            match s {
+                "global" => Ok(Numbering::Global),
+                "per-dir" => Ok(Numbering::PerDir),
+                _ => Err(format!("invalid argument '{}' for '--number'", s).into()),
+            }
+        }
+    }
+
+    #[derive(Clone, Copy, Debug, PartialEq)]
+    pub enum TotalPosition {
+        Before,
+        After,
+    }
+
+    impl FromStr for TotalPosition {
+        type Err = Box<dyn Error>;
+
+        fn from_str(s: &str) -> Result<Self, Self::Err> {
+            match s {
+                "before" => Ok(TotalPosition::Before),
+                "after" => Ok(TotalPosition::After),
+                _ => Err(format!("invalid argument '{}' for '--total-position'", s).into()),
+            }
+        }
+    }
+
+    #[derive(Clone, Copy, Debug, PartialEq)]
+    pub enum ThemeName {
+        Default,
+        Dark,
+        Light,
+        Solarized,
+    }
+
+    impl FromStr for ThemeName {
+        type Err = Box<dyn Error>;
+
+        fn from_str(s: &str) -> Result<Self, Self::Err> {
+            match s {
+                "default" => Ok(ThemeName::Default),
+                "dark" => Ok(ThemeName::Dark),
+                "light" => Ok(ThemeName::Light),
+                "solarized" => Ok(ThemeName::Solarized),
+                _ => Err(format!("invalid argument '{}' for '--theme'", s).into()),
+            }
+        }
+    }
+
+    #[derive(Clone, Copy, Debug, Default, PartialEq)]
+    pub enum SortField {
+        None,
+        #[default]
+        Name,
+        Size,
+        Time,
+        Extension,
+        Version,
+        /// Hard-link count, most-linked first.
+        Links,
+        /// Entries inside each directory, busiest first; files last.
+        ChildCount,
+    }
+
+    impl FromStr for SortField {
+        type Err = Box<dyn Error>;
+
+        fn from_str(s: &str) -> Result<Self, Self::Err> {
+            match s {
+                "none" => Ok(SortField::None),
+                "name" => Ok(SortField::Name),
+                "size" => Ok(SortField::Size),
+                "time" => Ok(SortField::Time),
+                "extension" => Ok(SortField::Extension),
+                "version" => Ok(SortField::Version),
+                "links" => Ok(SortField::Links),
+                "child-count" => Ok(SortField::ChildCount),
+                _ => Err(format!("invalid argument '{}' for '--sort'", s).into()),
+            }
+        }
+    }
+
+    /// How nongraphic characters in names are rendered.
+    #[derive(Clone, Copy, Debug, PartialEq)]
+    pub enum ControlChars {
+        /// `-b`: octal escapes.
+        Escape,
+        /// `-q`: replaced with `?`.
+        Hide,
+        /// `--show-control-chars`: written raw.
+        Show,
+    }
+
+    /// `-b` always wins; otherwise the later of `-q`/`--show-control-chars`
+    /// applies, and with neither the default depends on whether stdout is a
+    /// terminal, as in coreutils.
+    pub fn resolve_control_chars(args: &Arguments, is_tty: bool) -> ControlChars {
+        if args.escape {
+            ControlChars::Escape
+        } else if args.hide_control_chars {
+            ControlChars::Hide
+        } else if args.show_control_chars || !is_tty {
+            ControlChars::Show
+        } else {
+            ControlChars::Hide
+        }
+    }
+
+    /// Position of the last occurrence of `name` on the command line.
+    fn last_index(matches: &ArgMatches, name: &str) -> Option<usize> {
+        matches.indices_of(name).and_then(|indices| indices.max())
+    }
+
+    /// Time kind selected by `-c WHEN` or `-u`; the later one wins.
+    fn resolve_time(matches: &ArgMatches, args: &Arguments) -> Option<TimeSort> {
+        if !args.access_time {
+            return args.time;
+        }
+        match (
+            last_index(matches, "time"),
+            last_index(matches, "access-time"),
+        ) {
+            (Some(c), Some(u)) if c > u => args.time,
+            _ => Some(TimeSort::Atime),
+        }
+    }
+
+    /// `-1`, `-m`, `-C`, `-x` and `--format` are last-wins, as in GNU ls.
+    /// Without any of them the layout depends on the output: a vertical grid
+    /// on a terminal, one entry per line otherwise.
+    fn resolve_format(matches: &ArgMatches, args: &Arguments) -> Format {
+        let candidates = [
+            (
+                args.one_per_line.then_some(Format::SingleColumn),
+                "one-per-line",
+            ),
+            (args.commas.then_some(Format::Commas), "commas"),
+            (args.columns.then_some(Format::Vertical), "columns"),
+            (args.across.then_some(Format::Across), "across"),
+            (args.format, "format"),
+        ];
+        candidates
+            .iter()
+            .filter_map(|(format, name)| Some(((*format)?, last_index(matches, name)?)))
+            .max_by_key(|(_, index)| *index)
+            .map(|(format, _)| format)
+            .unwrap_or(if terminal::is_tty() {
+                Format::Vertical
+            } else {
+                Format::SingleColumn
+            })
+    }
+
+    /// Applies the GNU rules for the sort flags:
+    ///
+    /// - `-S`, `-t`, `-X`, `-v`, `-U` and `--sort` all select the sort key;
+    ///   the last one given wins.
+    /// - If none of them is given, choosing a time with `-c`/`-u` sorts by
+    ///   that time; otherwise entries are sorted by name.
+    /// - `-r` reverses any key, but reversing an unsorted listing (`-U` or
+    ///   `--sort=none`) is rejected as contradictory.
+    pub fn resolve_sort(
+        matches: &ArgMatches,
+        args: &Arguments,
+    ) -> Result<(SortField, bool), String> {
+        let candidates = [
+            (args.sort_size.then_some(SortField::Size), "sort-size"),
+            (args.sort_time.then_some(SortField::Time), "sort-time"),
+            (
+                args.sort_extension.then_some(SortField::Extension),
+                "sort-extension",
+            ),
+            (
+                args.sort_version.then_some(SortField::Version),
+                "sort-version",
+            ),
+            (args.sort_none.then_some(SortField::None), "sort-none"),
+            (args.sort, "sort"),
+        ];
+        let explicit = candidates
+            .iter()
+            .filter_map(|(field, name)| Some(((*field)?, last_index(matches, name)?)))
+            .max_by_key(|(_, index)| *index)
+            .map(|(field, _)| field);
+
+        let field = match explicit {
+            Some(field) => field,
+            None if args.time.is_some() => SortField::Time,
+            None => SortField::Name,
+        };
+
+        if field == SortField::None && args.reverse {
+            return Err("cannot reverse an unsorted listing (-r with -U/--sort=none)".to_string());
+        }
+
+        Ok((field, args.reverse))
+    }
+
+    /// `-F`, `-p` and `--indicator-style` combine by keeping the style with
+    /// the most indicators, so `-F` beats `-p` in either order.
+    fn resolve_indicator_style(args: &Arguments) -> IndicatorStyle {
+        [
+            args.classify.then_some(IndicatorStyle::Classify),
+            args.slash.then_some(IndicatorStyle::Slash),
+            args.indicator_style,
+        ]
+        .into_iter()
+        .flatten()
+        .fold(IndicatorStyle::None, |best, style| {
+            if style > best {
+                style
+            } else {
+                best
+            }
+        })
+    }
+
+    pub fn parse_args() -> Result<Arguments, Box<dyn Error>> {
+        let matches = Arguments::clap().get_matches();
+        let mut args = Arguments::from_clap(&matches);
+
+        args.escape |= args.escape_style.is_some();
+        args.time = resolve_time(&matches, &args);
+        args.format = Some(resolve_format(&matches, &args));
+        args.indicator_style = Some(resolve_indicator_style(&args));
+        match resolve_sort(&matches, &args) {
+            Ok((field, reverse)) => {
+                args.sort_field = field;
+                args.reverse = reverse;
+            }
+            Err(message) => {
+                clap::Error::with_description(&message, clap::ErrorKind::ArgumentConflict).exit()
+            }
+        }
+
+        Ok(args)
+    }
+}
+
+mod entries {
+    use std::ffi::OsString;
+    use std::fs::{self, FileType, Metadata};
+    use std::io;
+    use std::path::{Path, PathBuf};
+    use std::sync::OnceLock;
+    use std::time::{Duration, SystemTime};
+
+    /// One name read from a directory. It mirrors `std::fs::DirEntry`, but
+    /// comes from `read_names`, which can list a directory through the
+    /// descriptor that opened it rather than looking it up by path again.
+    #[derive(Clone, Debug)]
+    pub struct DirEntry {
+        path: PathBuf,
+        file_type: OnceLock<FileType>,
+    }
+
+    impl DirEntry {
+        fn new(dir: &Path, name: OsString) -> Self {
+            DirEntry {
+                path: dir.join(name),
+                file_type: OnceLock::new(),
+            }
+        }
+
+        /// An entry for a path named directly, such as a command-line operand.
+        pub fn from_path(path: &Path) -> Self {
+            DirEntry {
+                path: path.to_path_buf(),
+                file_type: OnceLock::new(),
+            }
+        }
+
+        pub fn path(&self) -> PathBuf {
+            self.path.clone()
+        }
+
+        /// The final component, or the whole path for one without a name of
+        /// its own such as `.` or `..`.
+        pub fn file_name(&self) -> OsString {
+            self.path
+                .file_name()
+                .unwrap_or(self.path.as_os_str())
+                .to_os_string()
+        }
+
+        /// Metadata of the entry itself; symlinks are not traversed.
+        pub fn metadata(&self) -> io::Result<Metadata> {
+            fs::symlink_metadata(&self.path)
+        }
+
+        pub fn file_type(&self) -> io::Result<FileType> {
+            if let Some(file_type) = self.file_type.get() {
+                return Ok(*file_type);
+            }
+            let file_type = self.metadata()?.file_type();
+            Ok(*self.file_type.get_or_init(|| file_type))
+        }
+    }
+
+    /// Names in the directory at `path`, whose last `nofollow` components
+    /// lie below the listing root. The root is opened by path; each of
+    /// those components is then opened with `openat` from its parent's
+    /// descriptor and `O_NOFOLLOW`, so a symlink put in place of the
+    /// directory or of any ancestor below the root fails instead of being
+    /// followed, and the names are read from the last descriptor.
+    ///
+    /// Entry metadata is still read by path afterwards (`std` offers no
+    /// `Metadata` from `fstatat`), so an ancestor swapped after this read
+    /// can make it describe another file, though the walk never descends
+    /// there.
+    #[cfg(unix)]
+    fn read_names(path: &Path, nofollow: usize) -> io::Result<Vec<OsString>> {
+        use std::ffi::{CStr, CString, OsStr};
+        use std::os::fd::{AsRawFd, FromRawFd, IntoRawFd, OwnedFd};
+        use std::os::unix::ffi::OsStrExt;
+
+        let root = path.ancestors().nth(nofollow).unwrap_or(path);
+        let mut below: Vec<&OsStr> = Vec::with_capacity(nofollow);
+        for ancestor in path.ancestors().take(nofollow) {
+            let name = ancestor.file_name().ok_or_else(|| {
+                io::Error::new(io::ErrorKind::InvalidInput, "not a directory name")
+            })?;
+            below.push(name);
+        }
+
+        let c_root = CString::new(root.as_os_str().as_bytes())?;
+        let flags = libc::O_RDONLY | libc::O_DIRECTORY | libc::O_CLOEXEC;
+        // SAFETY: `c_root` is a valid NUL-terminated string.
+        let fd = unsafe { libc::open(c_root.as_ptr(), flags) };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        // SAFETY: `fd` was just opened and is owned by nothing else.
+        let mut fd = unsafe { OwnedFd::from_raw_fd(fd) };
+        for name in below.into_iter().rev() {
+            let c_name = CString::new(name.as_bytes())?;
+            // SAFETY: `fd` is open and `c_name` is NUL-terminated.
+            let child =
+                unsafe { libc::openat(fd.as_raw_fd(), c_name.as_ptr(), flags | libc::O_NOFOLLOW) };
+            if child < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            // SAFETY: as above; the parent's descriptor is closed on drop.
+            fd = unsafe { OwnedFd::from_raw_fd(child) };
+        }
+
+        let fd = fd.into_raw_fd();
+        // SAFETY: `fd` is open; on success the stream owns it.
+        let dir = unsafe { libc::fdopendir(fd) };
+        if dir.is_null() {
+            let error = io::Error::last_os_error();
+            // SAFETY: `fdopendir` failed, so `fd` is still ours to close.
+            unsafe { libc::close(fd) };
+            return Err(error);
+        }
+        let mut names = Vec::new();
+        let result = loop {
+            // `readdir` returns NULL both at the end and on error; only an
+            // error sets errno.
+            errno::clear();
+            // SAFETY: `dir` is an open stream; the returned entry is only
+            // read before the next call.
+            let entry = unsafe { libc::readdir(dir) };
+            if entry.is_null() {
+                let error = io::Error::last_os_error();
+                break match error.raw_os_error() {
+                    Some(0) | None => Ok(names),
+                    Some(_) => Err(error),
+                };
+            }
+            let name = unsafe { CStr::from_ptr((*entry).d_name.as_ptr()) }.to_bytes();
+            if name != b"." && name != b".." {
+                names.push(OsStr::from_bytes(name).to_os_string());
+            }
+        };
+        // SAFETY: `dir` is open and closed exactly once, along with `fd`.
+        unsafe { libc::closedir(dir) };
+        result
+    }
+
+    #[cfg(unix)]
+    mod errno {
+        #[cfg(any(target_os = "openbsd", target_os = "netbsd"))]
+        use libc::__errno as location;
+        #[cfg(any(target_os = "linux", target_os = "android", target_os = "emscripten"))]
+        use libc::__errno_location as location;
+        #[cfg(any(
+            target_os = "macos",
+            target_os = "ios",
+            target_os = "freebsd",
+            target_os = "dragonfly"
+        ))]
+        use libc::__error as location;
+
+        /// Resets errno, for calls that report errors only through it.
+        pub fn clear() {
+            // SAFETY: errno is thread-local and always writable.
+            unsafe { *location() = 0 };
+        }
+    }
+
+    /// Without `O_NOFOLLOW`, directories are read by path as they are.
+    #[cfg(not(unix))]
+    fn read_names(path: &Path, _nofollow: usize) -> io::Result<Vec<OsString>> {
+        Ok(fs::read_dir(path)?
+            .filter_map(|res| res.ok())
+            .map(|entry| entry.file_name())
+            .collect())
+    }
+
+    /// Access checks requested with `--only-readable`/`--only-writable`/
+    /// `--only-executable`; every requested check must pass.
+    #[derive(Clone, Copy, Debug, Default)]
+    pub struct AccessFilter {
+        pub readable: bool,
+        pub writable: bool,
+        pub executable: bool,
+    }
+
+    impl AccessFilter {
+        fn matches(&self, path: &Path) -> bool {
+            (!self.readable || access::readable(path))
+                && (!self.writable || access::writable(path))
+                && (!self.executable || access::executable(path))
+        }
+    }
+
+    /// Modification-time window from `--modified-within`/`--modified-before`.
+    #[derive(Clone, Copy, Debug, Default)]
+    pub struct ModifiedFilter {
+        pub within: Option<Duration>,
+        pub before: Option<Duration>,
+    }
+
+    impl ModifiedFilter {
+        fn matches(&self, entry: &DirEntry, now: SystemTime) -> bool {
+            if self.within.is_none() && self.before.is_none() {
+                return true;
+            }
+            let age = match entry.metadata().and_then(|metadata| metadata.modified()) {
+                // Entries modified in the future have an age of zero.
+                Ok(modified) => now.duration_since(modified).unwrap_or_default(),
+                Err(_) => return false,
+            };
+            self.within.is_none_or(|within| age <= within)
+                && self.before.is_none_or(|before| age > before)
+        }
+    }
+
+    /// Size bounds from `--larger-than`/`--smaller-than`, both exclusive.
+    /// Directories are judged by their own entry size, not their contents.
+    #[derive(Clone, Copy, Debug, Default)]
+    pub struct SizeFilter {
+        pub larger_than: Option<u64>,
+        pub smaller_than: Option<u64>,
+    }
+
+    impl SizeFilter {
+        fn matches(&self, entry: &DirEntry) -> bool {
+            if self.larger_than.is_none() && self.smaller_than.is_none() {
+                return true;
+            }
+            let len = match entry.metadata() {
+                Ok(metadata) => metadata.len(),
+                Err(_) => return false,
+            };
+            self.larger_than.is_none_or(|min| len > min)
+                && self.smaller_than.is_none_or(|max| len < max)
+        }
+    }
+
+    /// Negative filters from `--exclude`/`--exclude-owner`: an entry is kept
+    /// only if it matches none of them. They apply to directories too, so
+    /// `-R` does not descend into an excluded one.
+    #[derive(Clone, Copy, Debug, Default)]
+    pub struct ExcludeFilter<'a> {
+        pub patterns: &'a [String],
+        /// Ignore-file rules, lowest precedence first; see `ignored`.
+        pub ignore_rules: &'a [String],
+        pub owners: &'a [u32],
+    }
+
+    impl ExcludeFilter<'_> {
+        fn matches(&self, entry: &DirEntry) -> bool {
+            let name = entry.file_name();
+            let name = name.to_string_lossy();
+            !self
+                .patterns
+                .iter()
+                .any(|pattern| glob_match(pattern, &name))
+                && !self.ignored(&name)
+                && (self.owners.is_empty()
+                    || entry
+                        .metadata()
+                        .is_ok_and(|metadata| !self.owners.contains(&owner(&metadata))))
+        }
+
+        /// Whether the ignore rules hide `name`: the last rule matching it
+        /// decides, so a later `!GLOB` re-includes what an earlier rule hid
+        /// and vice versa. `\!` starts a glob that begins with a literal `!`.
+        fn ignored(&self, name: &str) -> bool {
+            self.ignore_rules
+                .iter()
+                .rev()
+                .find_map(|rule| match rule.strip_prefix('!') {
+                    Some(glob) => glob_match(glob, name).then_some(false),
+                    None => {
+                        let glob = rule.strip_prefix('\\').unwrap_or(rule);
+                        glob_match(glob, name).then_some(true)
+                    }
+                })
+                .unwrap_or(false)
+        }
+    }
+
+    #[cfg(unix)]
+    fn owner(metadata: &fs::Metadata) -> u32 {
+        use std::os::unix::fs::MetadataExt;
+        metadata.uid()
+    }
+
+    #[cfg(not(unix))]
+    fn owner(_metadata: &fs::Metadata) -> u32 {
+        u32::MAX
+    }
+
+    /// Shell-style match of a whole name: `*` is any run of characters, `?`
+    /// any one, and `[...]` a set with `a-z` ranges, negated by a leading `!`
+    /// or `^`. A leading `.` is not special.
+    pub fn glob_match(pattern: &str, name: &str) -> bool {
+        let pattern: Vec<char> = pattern.chars().collect();
+        let name: Vec<char> = name.chars().collect();
+        // Iterative matching with backtracking to the most recent `*`.
+        let (mut p, mut n) = (0, 0);
+        let mut star: Option<(usize, usize)> = None;
+        while n < name.len() {
+            match pattern.get(p) {
+                Some('*') => {
+                    star = Some((p, n));
+                    p += 1;
+                    continue;
+                }
+                Some('?') => {
+                    p += 1;
+                    n += 1;
+                    continue;
+                }
+                Some('[') => {
+                    if let Some((matched, end)) = match_set(&pattern[p..], name[n]) {
+                        if matched {
+                            p += end;
+                            n += 1;
+                            continue;
+                        }
+                    } else if name[n] == '[' {
+                        // An unterminated set is a literal '['.
+                        p += 1;
+                        n += 1;
+                        continue;
+                    }
+                }
+                Some(&c) if c == name[n] => {
+                    p += 1;
+                    n += 1;
+                    continue;
+                }
+                _ => {}
+            }
+            match star {
+                Some((star_p, star_n)) => {
+                    p = star_p + 1;
+                    n = star_n + 1;
+                    star = Some((star_p, star_n + 1));
+                }
+                None => return false,
+            }
+        }
+        pattern[p..].iter().all(|&c| c == '*')
+    }
+
+    /// Matches `c` against the set at the start of `pattern`, returning
+    /// whether it matched and the set's length, or `None` if unterminated.
+    fn match_set(pattern: &[char], c: char) -> Option<(bool, usize)> {
+        let mut i = 1;
+        let negated = matches!(pattern.get(i), Some('!') | Some('^'));
+        if negated {
+            i += 1;
+        }
+        let mut matched = false;
+        let mut first = true;
+        loop {
+            let start = *pattern.get(i)?;
+            // A ']' right after the opening bracket is a member.
+            if start == ']' && !first {
+                return Some((matched != negated, i + 1));
+            }
+            first = false;
+            if pattern.get(i + 1) == Some(&'-') && pattern.get(i + 2).is_some_and(|&e| e != ']') {
+                matched |= (start..=pattern[i + 2]).contains(&c);
+                i += 3;
+            } else {
+                matched |= start == c;
+                i += 1;
+            }
+        }
+    }
+
+    /// Type filters from `--symlinks-only`/`--broken-symlinks`. Unlike the
+    /// other filters these are applied by the walker, after it has chosen
+    /// which subdirectories `-R` descends into.
+    #[derive(Clone, Copy, Debug, Default)]
+    pub struct LinkFilter {
+        pub symlinks_only: bool,
+        pub broken_only: bool,
+    }
+
+    impl LinkFilter {
+        pub fn is_active(&self) -> bool {
+            self.symlinks_only || self.broken_only
+        }
+
+        pub fn matches(&self, entry: &DirEntry) -> bool {
+            if !self.is_active() {
+                return true;
+            }
+            // `DirEntry::file_type` does not follow symlinks.
+            let is_symlink = entry.file_type().is_ok_and(|t| t.is_symlink());
+            is_symlink && (!self.broken_only || fs::metadata(entry.path()).is_err())
+        }
+    }
+
+    /// Every entry of the directory at `path`; see `read_names`.
+    pub fn read_entries(path: &Path, nofollow: usize) -> io::Result<Vec<DirEntry>> {
+        Ok(read_names(path, nofollow)?
+            .into_iter()
+            .map(|name| DirEntry::new(path, name))
+            .collect())
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    pub fn filter_entries(
+        entries: Vec<DirEntry>,
+        show_hidden: bool,
+        show_almost_all: bool,
+        access: AccessFilter,
+        modified: ModifiedFilter,
+        size: SizeFilter,
+        exclude: ExcludeFilter,
+        filter_dirs: bool,
+    ) -> Vec<DirEntry> {
+        let now = SystemTime::now();
+        entries
+            .into_iter()
+            .filter(|entry| {
+                if show_hidden {
+                    true
+                } else if show_almost_all {
+                    entry
+                        .file_name()
+                        .to_str()
+                        .map(|s| s != "." && s != "..")
+                        .unwrap_or(true)
+                } else {
+                    !entry
+                        .file_name()
+                        .to_str()
+                        .map(|s| s.starts_with('.'))
+                        .unwrap_or(false)
+                }
+            })
+            .filter(|entry| exclude.matches(entry))
+            .filter(|entry| {
+                // With `filter_dirs` off, directories always pass so that
+                // `--prune-empty` can judge them by what lies beneath.
+                (!filter_dirs && entry.file_type().is_ok_and(|t| t.is_dir()))
+                    || (access.matches(&entry.path())
+                        && modified.matches(entry, now)
+                        && size.matches(entry))
+            })
+            .collect()
+    }
+
+    /// Effective access for the current user. On Unix this asks the kernel
+    /// via `access(2)`, so ownership, groups and ACLs are all accounted for.
+    #[cfg(unix)]
+    mod access {
+        use std::ffi::CString;
+        use std::os::unix::ffi::OsStrExt;
+        use std::path::Path;
+
+        fn check(path: &Path, mode: libc::c_int) -> bool {
+            match CString::new(path.as_os_str().as_bytes()) {
+                Ok(path) => unsafe { libc::access(path.as_ptr(), mode) == 0 },
+                Err(_) => false,
+            }
+        }
+
+        pub fn readable(path: &Path) -> bool {
+            check(path, libc::R_OK)
+        }
+
+        pub fn writable(path: &Path) -> bool {
+            check(path, libc::W_OK)
+        }
+
+        pub fn executable(path: &Path) -> bool {
+            check(path, libc::X_OK)
+        }
+    }
+
+    /// Approximation from metadata: Windows has no execute bit, so
+    /// executability is judged by extension.
+    #[cfg(not(unix))]
+    mod access {
+        use std::fs;
+        use std::path::Path;
+
+        pub fn readable(path: &Path) -> bool {
+            fs::metadata(path).is_ok()
+        }
+
+        pub fn writable(path: &Path) -> bool {
+            fs::metadata(path)
+                .map(|metadata| !metadata.permissions().readonly())
+                .unwrap_or(false)
+        }
+
+        pub fn executable(path: &Path) -> bool {
+            if path.is_dir() {
+                return true;
+            }
+            path.extension()
+                .and_then(|ext| ext.to_str())
+                .map(|ext| {
+                    ["exe", "com", "bat", "cmd", "ps1"]
+                        .iter()
+                        .any(|known| ext.eq_ignore_ascii_case(known))
+                })
+                .unwrap_or(false)
+        }
+    }
+}
+
+mod list {
+    use chrono::{DateTime, Local};
+    use std::borrow::Cow;
+    use std::collections::{HashMap, HashSet};
+    use std::error::Error;
+    use std::ffi::{OsStr, OsString};
+    use std::fmt::Write;
+    use std::fs::{self, Metadata};
+    use std::io;
+    use std::path::{Path, PathBuf};
+
+    use unicode_width::{UnicodeWidthChar, UnicodeWidthStr};
+
+    use crate::args::{
+        ColorBy, ControlChars, EscapeStyle, Format, IndicatorStyle, TimeSort, TimeStyle, TimeUnit,
+        TotalPosition,
+    };
+    use crate::entries::DirEntry;
+    use crate::{color, size, summary, terminal};
+
+    /// Upper bound on symlink hops, matching Linux's `MAXSYMLINKS`.
+    const MAX_SYMLINK_HOPS: usize = 40;
+
+    #[derive(Clone, Debug)]
+    pub struct ListOptions {
+        pub control_chars: ControlChars,
+        pub time: Option<TimeSort>,
+        pub indicator_style: IndicatorStyle,
+        pub relative_to: Option<DateTime<Local>>,
+        /// `--time-style` and the "now" it measures recency from.
+        pub time_style: Option<(TimeStyle, DateTime<Local>)>,
+        /// Finest unit for relative times; `None` shows only the coarsest.
+        pub relative_granularity: Option<TimeUnit>,
+        pub hide_extension: bool,
+        pub symlink_chain: bool,
+        pub blocks: bool,
+        /// `--align-ext`: pad stems so extensions start in one column.
+        pub align_ext: bool,
+        /// `--mark-hardlinks`: tag names that share an inode with `[#N]`.
+        pub mark_hardlinks: bool,
+        /// `--number`: prefix entries with their index in the listing.
+        pub number: bool,
+        /// Where `-s` prints its `total` line.
+        pub total_position: TotalPosition,
+        pub device_id: bool,
+        pub mark_mounts: bool,
+        /// Spell out terminal escape sequences in names; see `strip_ansi`.
+        pub sanitize_names: bool,
+        /// `-L`: describe symlink targets rather than the links.
+        pub dereference: bool,
+        /// Byte notation for `-b`.
+        pub escape_style: EscapeStyle,
+        /// Check that every name survives a `-b` escape round trip.
+        pub verify_quoting: bool,
+        pub format: Format,
+        /// `--width`; `None` means ask the terminal.
+        pub width: Option<usize>,
+        /// `--wrap-names`: fold one-per-line output at the width.
+        pub wrap_names: bool,
+        /// Coloring scheme, or `None` when color is disabled.
+        pub color: Option<ColorBy>,
+        /// Palette for `ColorBy::Type`.
+        pub theme: color::Theme,
+        /// `--smart-exec-color`: sniff content before coloring as executable.
+        pub smart_exec: bool,
+        /// The "now" `--color-by=age` measures from: `--at`, else the clock.
+        pub now: DateTime<Local>,
+        /// `--file-size`: a byte-size column before each name.
+        pub file_size: bool,
+        /// `--percent`: each entry's share of the group's total size.
+        pub percent: bool,
+        /// Fixed widths for the `--device-id`, `-s` and `--file-size`
+        /// columns; `None` sizes a column to its widest value.
+        pub device_width: Option<usize>,
+        pub blocks_width: Option<usize>,
+        pub size_width: Option<usize>,
+        /// Cut values wider than a fixed width instead of letting them
+        /// overflow.
+        pub truncate_columns: bool,
+        /// `-h`: humanize size columns.
+        pub human: bool,
+        /// `--zero`: NUL-terminated, unpadded records.
+        pub zero: bool,
+        /// Show each name with its directory, for `--files-from` and `-d0`.
+        pub full_paths: bool,
+        /// `--paths-only`: raw paths and nothing else.
+        pub paths_only: bool,
+    }
+
+    impl ListOptions {
+        /// Line terminator: NUL with `--zero`, newline otherwise.
+        pub fn eol(&self) -> char {
+            if self.zero {
+                '\0'
+            } else {
+                '\n'
+            }
+        }
+    }
+
+    /// How a symlink chain ended.
+    #[derive(Clone, Copy, Debug, PartialEq)]
+    pub enum ChainEnd {
+        Target,
+        Broken,
+        Cycle,
+        TooDeep,
+    }
+
+    /// Prints one directory's entries. `listed` counts entries printed so far
+    /// and numbers them for `--number`; callers reset it to restart numbering.
+    pub fn list_dir(
+        entries: &[DirEntry],
+        options: &ListOptions,
+        listed: &mut usize,
+    ) -> Result<(), Box<dyn Error>> {
+        if options.paths_only {
+            return list_paths(entries, options, listed);
+        }
+        // The whole group is formatted first and written at once, so it can
+        // never interleave with output from elsewhere.
+        let out = format_dir(entries, options, listed)?;
+        io::Write::write_all(&mut io::stdout().lock(), out.as_bytes())?;
+        Ok(())
+    }
+
+    /// `--paths-only`: each entry's path, byte for byte, so names that are
+    /// not valid UTF-8 survive a pipe; `-b`, `-q` and the like do not apply.
+    fn list_paths(
+        entries: &[DirEntry],
+        options: &ListOptions,
+        listed: &mut usize,
+    ) -> Result<(), Box<dyn Error>> {
+        let mut out = Vec::new();
+        for entry in entries {
+            out.extend_from_slice(&os_bytes(entry.path().as_os_str()));
+            out.push(options.eol() as u8);
+        }
+        *listed += entries.len();
+        io::Write::write_all(&mut io::stdout().lock(), &out)?;
+        Ok(())
+    }
+
+    /// Formats one directory's entries as `list_dir` prints them.
+    pub fn format_dir(
+        entries: &[DirEntry],
+        options: &ListOptions,
+        listed: &mut usize,
+    ) -> Result<String, Box<dyn Error>> {
+        let mut out = String::new();
+        // Right-aligned columns printed before each name, in order, each
+        // with its fixed width if one was given.
+        let mut columns: Vec<(Vec<String>, Option<usize>)> = Vec::new();
+        let mut total = None;
+        let first = *listed + 1;
+        *listed += entries.len();
+        if options.number {
+            // Aligned to the largest index in this group; later groups of a
+            // global count may be wider.
+            let numbers = (first..=*listed).map(|n| n.to_string()).collect();
+            columns.push((numbers, None));
+        }
+        if options.device_id {
+            let mut ids = Vec::with_capacity(entries.len());
+            for entry in entries {
+                ids.push(device_id(&metadata(entry, options)?));
+            }
+            columns.push((ids, options.device_width));
+        }
+        if options.blocks {
+            let mut allocated = Vec::with_capacity(entries.len());
+            for entry in entries {
+                allocated.push(allocated_bytes(&metadata(entry, options)?));
+            }
+            // Like GNU, the total is the summed allocation scaled once, not
+            // a sum of rounded counts.
+            let sum = size::scaled(allocated.iter().sum(), BLOCK_SIZE, options.human);
+            if options.total_position == TotalPosition::Before {
+                write!(out, "total {}{}", sum, options.eol())?;
+            }
+            total = Some(sum);
+            columns.push((
+                allocated
+                    .iter()
+                    .map(|&bytes| size::scaled(bytes, BLOCK_SIZE, options.human))
+                    .collect(),
+                options.blocks_width,
+            ));
+        }
+        if options.file_size {
+            let mut sizes = Vec::with_capacity(entries.len());
+            for entry in entries {
+                let len = metadata(entry, options)?.len();
+                sizes.push(size::scaled(len, 1, options.human));
+            }
+            columns.push((sizes, options.size_width));
+        }
+        let widths: Vec<usize> = columns
+            .iter()
+            .map(|(column, fixed)| {
+                fixed.unwrap_or_else(|| {
+                    column
+                        .iter()
+                        .map(|cell| display_width(cell))
+                        .max()
+                        .unwrap_or(0)
+                })
+            })
+            .collect();
+
+        let stem_width = if options.align_ext {
+            let mut widest = 0;
+            for entry in entries {
+                let name = display_name(&entry_name(entry, options)?, options);
+                if let Some((stem, _)) = split_extension(&name) {
+                    widest = widest.max(display_width(stem));
+                }
+            }
+            Some(widest)
+        } else {
+            None
+        };
+
+        let link_groups = if options.mark_hardlinks {
+            hard_link_groups(entries, options)?
+        } else {
+            Vec::new()
+        };
+
+        let shares = if options.percent {
+            size_shares(entries, options)?
+        } else {
+            Vec::new()
+        };
+
+        let mut lines = Vec::with_capacity(entries.len());
+        // Column where each line's name starts, for `--wrap-names`.
+        let mut indents = Vec::with_capacity(entries.len());
+        for (index, entry) in entries.iter().enumerate() {
+            let mut name = String::new();
+            render_entry(&mut name, entry, options, stem_width)?;
+            if let Some(group) = link_groups.get(index).copied().flatten() {
+                write!(name, " [#{}]", group)?;
+            }
+            let time = render_time(entry, options)?;
+            let share = shares.get(index).map(|share| format!("({:.1}%)", share));
+
+            if options.zero {
+                // No padding: fields are NUL-separated, and a record with more
+                // than one field ends in a double NUL.
+                let mut fields: Vec<&str> =
+                    columns.iter().map(|(c, _)| c[index].as_str()).collect();
+                fields.push(&name);
+                fields.extend(time.as_deref());
+                fields.extend(share.as_deref());
+                let mut record = fields.join("\0");
+                if fields.len() > 1 {
+                    record.push('\0');
+                }
+                lines.push(record);
+                continue;
+            }
+
+            let mut line = String::new();
+            for ((column, _), &width) in columns.iter().zip(&widths) {
+                let cell = &column[index];
+                let cell_width = display_width(cell);
+                if cell_width <= width {
+                    write!(
+                        line,
+                        "{:padding$}{} ",
+                        "",
+                        cell,
+                        padding = width - cell_width
+                    )?;
+                } else if options.truncate_columns {
+                    write!(line, "{} ", truncate(cell, width))?;
+                } else {
+                    // Overflow: the value is never cut, the row just shifts.
+                    write!(line, "{} ", cell)?;
+                }
+            }
+            indents.push(display_width(&line));
+            line.push_str(&name);
+            if let Some(time) = time {
+                write!(line, "  {}", time)?;
+            }
+            if let Some(share) = share {
+                write!(line, "  {}", share)?;
+            }
+            lines.push(line);
+        }
+
+        match options.format {
+            // One entry per line never needs the terminal width.
+            Format::SingleColumn if options.wrap_names && !options.zero => {
+                let width = terminal::width(options.width);
+                for (line, &indent) in lines.iter().zip(&indents) {
+                    for piece in wrap_line(line, width, indent) {
+                        writeln!(out, "{}", piece)?;
+                    }
+                }
+            }
+            Format::SingleColumn => {
+                for line in &lines {
+                    write!(out, "{}{}", line, options.eol())?;
+                }
+            }
+            Format::Commas => format_commas(&mut out, &lines, terminal::width(options.width)),
+            Format::Vertical => format_grid(&mut out, &lines, terminal::width(options.width), true),
+            Format::Across => format_grid(&mut out, &lines, terminal::width(options.width), false),
+        }
+        if let (Some(total), TotalPosition::After) = (total, options.total_position) {
+            write!(out, "total {}{}", total, options.eol())?;
+        }
+
+        Ok(out)
+    }
+
+    /// `--mark-hardlinks` group for each entry: files whose inode has more
+    /// than one link are numbered by inode, in order of first appearance in
+    /// this listing.
+    fn hard_link_groups(
+        entries: &[DirEntry],
+        options: &ListOptions,
+    ) -> Result<Vec<Option<usize>>, Box<dyn Error>> {
+        let mut groups: HashMap<(u64, u64), usize> = HashMap::new();
+        let mut marks = Vec::with_capacity(entries.len());
+        for entry in entries {
+            let mark = summary::hard_link_key(&metadata(entry, options)?).map(|key| {
+                let next = groups.len() + 1;
+                *groups.entry(key).or_insert(next)
+            });
+            marks.push(mark);
+        }
+        Ok(marks)
+    }
+
+    /// `--percent`: each entry's size as a percentage of the group's total
+    /// size. An all-empty group is 0% throughout.
+    fn size_shares(
+        entries: &[DirEntry],
+        options: &ListOptions,
+    ) -> Result<Vec<f64>, Box<dyn Error>> {
+        let mut sizes = Vec::with_capacity(entries.len());
+        for entry in entries {
+            sizes.push(metadata(entry, options)?.len());
+        }
+        let total = sizes.iter().sum::<u64>();
+        Ok(sizes
+            .into_iter()
+            .map(|len| {
+                if total == 0 {
+                    0.0
+                } else {
+                    len as f64 * 100.0 / total as f64
+                }
+            })
+            .collect())
+    }
+
+    /// Gap between grid columns, as in GNU ls.
+    const COLUMN_GAP: usize = 2;
+
+    /// Column widths chosen by `compute_columns`, each including the gap
+    /// that follows it (the last column's gap is counted but never printed).
+    #[derive(Debug, PartialEq)]
+    pub struct Layout {
+        pub widths: Vec<usize>,
+    }
+
+    impl Layout {
+        pub fn columns(&self) -> usize {
+            self.widths.len()
+        }
+    }
+
+    /// GNU's grid fitting for `-C`/`-x`. Every column count up to what
+    /// could fit is tried at once, each column as wide as its widest entry
+    /// plus `min_gap` (no gap after the last column), and the most columns
+    /// whose summed widths fit strictly within `term_width` win; one column
+    /// if nothing fits. Vertical grids fill column by column with the same
+    /// number of rows in each; across grids fill row by row. A width of 0
+    /// never wraps.
+    pub fn compute_columns(
+        widths: &[usize],
+        term_width: usize,
+        min_gap: usize,
+        vertical: bool,
+    ) -> Layout {
+        let count = widths.len();
+        // Narrowest a column can be: one character plus the gap.
+        let min_column = min_gap + 1;
+        let max_columns = if term_width == 0 {
+            count
+        } else {
+            (term_width / min_column).clamp(1, count.max(1))
+        };
+
+        // Per candidate column count: total line length, column widths and
+        // whether it still fits.
+        let mut candidates: Vec<(usize, Vec<usize>, bool)> = (1..=max_columns)
+            .map(|cols| (cols * min_column, vec![min_column; cols], true))
+            .collect();
+        for (index, &length) in widths.iter().enumerate() {
+            for (i, (line_length, column_widths, fits)) in candidates.iter_mut().enumerate() {
+                if !*fits {
+                    continue;
+                }
+                let cols = i + 1;
+                let column = if vertical {
+                    index / count.div_ceil(cols)
+                } else {
+                    index % cols
+                };
+                let needed = length + if column == i { 0 } else { min_gap };
+                if column_widths[column] < needed {
+                    *line_length += needed - column_widths[column];
+                    column_widths[column] = needed;
+                    *fits = term_width == 0 || *line_length < term_width;
+                }
+            }
+        }
+        let widths = candidates
+            .into_iter()
+            .rev()
+            .find(|(_, _, fits)| *fits)
+            .map_or_else(|| vec![0], |(_, widths, _)| widths);
+        Layout { widths }
+    }
+
+    /// Lays `lines` out in a `compute_columns` grid.
+    fn format_grid(output: &mut String, lines: &[String], width: usize, vertical: bool) {
+        if lines.is_empty() {
+            return;
+        }
+        let lengths: Vec<usize> = lines.iter().map(|line| display_width(line)).collect();
+        let count = lines.len();
+        let layout = compute_columns(&lengths, width, COLUMN_GAP, vertical);
+        let cols = layout.columns();
+        let rows = count.div_ceil(cols);
+
+        for row in 0..rows {
+            let cells: Vec<usize> = if vertical {
+                (0..cols)
+                    .map(|col| col * rows + row)
+                    .filter(|&index| index < count)
+                    .collect()
+            } else {
+                (row * cols..count.min((row + 1) * cols)).collect()
+            };
+            for (col, &index) in cells.iter().enumerate() {
+                output.push_str(&lines[index]);
+                if col + 1 < cells.len() {
+                    let padding = layout.widths[col] - lengths[index];
+                    output.extend(std::iter::repeat_n(' ', padding));
+                }
+            }
+            output.push('\n');
+        }
+    }
+
+    /// GNU's `-m` packing: entries are joined with `, ` and a line is broken
+    /// after the comma once the next entry would not fit. A width of 0 never
+    /// wraps.
+    fn format_commas(output: &mut String, lines: &[String], width: usize) {
+        let mut pos = 0;
+        for (index, line) in lines.iter().enumerate() {
+            let len = display_width(line);
+            if index != 0 {
+                if width == 0 || pos + len + 2 < width {
+                    output.push_str(", ");
+                    pos += 2;
+                } else {
+                    output.push_str(",\n");
+                    pos = 0;
+                }
+            }
+            output.push_str(line);
+            pos += len;
+        }
+        output.push('\n');
+    }
+
+    /// Width of `s` on screen, ignoring SGR color sequences.
+    pub fn display_width(s: &str) -> usize {
+        let mut width = 0;
+        let mut rest = s;
+        while let Some(start) = rest.find('\x1b') {
+            width += UnicodeWidthStr::width(&rest[..start]);
+            rest = match rest[start..].find('m') {
+                Some(end) => &rest[start + end + 1..],
+                None => "",
+            };
+        }
+        width + UnicodeWidthStr::width(rest)
+    }
+
+    /// The name to show for `entry` before any escaping: the last path
+    /// component, or the full path for manifests and `--max-depth=0`.
+    fn entry_name(entry: &DirEntry, options: &ListOptions) -> Result<OsString, Box<dyn Error>> {
+        let path = entry.path();
+        let mut file_name: OsString = path.components().next_back().unwrap().as_os_str().into();
+
+        if options.hide_extension && !entry.file_type()?.is_dir() {
+            if let Some(stem) = path.file_stem() {
+                file_name = stem.into();
+            }
+        }
+
+        if options.full_paths {
+            let dir = path.parent().unwrap_or(Path::new(""));
+            if dir != Path::new(".") {
+                file_name = dir.join(&file_name).into_os_string();
+            }
+        }
+        Ok(file_name)
+    }
+
+    /// `file_name` made safe for the terminal per `-b`/`-q`/sanitizing.
+    fn display_name(file_name: &OsStr, options: &ListOptions) -> String {
+        // -b already renders escape sequences harmless, byte for byte.
+        match options.control_chars {
+            ControlChars::Escape => escape_bytes(&os_bytes(file_name), options.escape_style),
+            _ => {
+                let mut name = file_name.to_string_lossy().into_owned();
+                if options.sanitize_names {
+                    name = strip_ansi(&name);
+                }
+                match options.control_chars {
+                    ControlChars::Hide => hide_control(&name),
+                    _ => name,
+                }
+            }
+        }
+    }
+
+    /// Splits a displayed name before its extension for `--align-ext`.
+    /// Names without a dot, dotfiles such as `.bashrc`, and dots in the
+    /// directory part of a full path do not count.
+    fn split_extension(name: &str) -> Option<(&str, &str)> {
+        let base = name.rfind('/').map_or(0, |slash| slash + 1);
+        match name[base..].rfind('.') {
+            Some(0) | None => None,
+            Some(dot) => Some(name.split_at(base + dot)),
+        }
+    }
+
+    /// Writes one entry's name and its decorations. With `stem_width`, the
+    /// part before the extension is padded to it so extensions line up.
+    fn render_entry(
+        out: &mut String,
+        entry: &DirEntry,
+        options: &ListOptions,
+        stem_width: Option<usize>,
+    ) -> Result<(), Box<dyn Error>> {
+        let path = entry.path();
+        let file_name = entry_name(entry, options)?;
+
+        if options.verify_quoting {
+            let raw = os_bytes(&file_name);
+            let escaped = escape_bytes(&raw, options.escape_style);
+            if unescape_name(&escaped).as_deref() != Ok(&raw[..]) {
+                eprintln!(
+                    "lsr: quoting mismatch: {:?} escapes to {}",
+                    file_name, escaped
+                );
+            }
+        }
+
+        let mut display_name = display_name(&file_name, options);
+        if let Some(width) = stem_width {
+            if let Some((stem, ext)) = split_extension(&display_name) {
+                let padding = width.saturating_sub(display_width(stem));
+                display_name = format!("{}{:padding$}{}", stem, "", ext, padding = padding);
+            }
+        }
+
+        match options.color {
+            Some(scheme) => {
+                let metadata = metadata(entry, options)?;
+                match color::color_for(
+                    &path,
+                    &metadata,
+                    scheme,
+                    &options.theme,
+                    options.smart_exec,
+                    options.now.into(),
+                ) {
+                    Some(code) => write!(out, "{}", color::paint(&code, &display_name))?,
+                    None => write!(out, "{}", display_name)?,
+                }
+            }
+            None => write!(out, "{}", display_name)?,
+        }
+
+        // Indicators are structure rather than decoration, so they do not
+        // depend on color being enabled.
+        match options.indicator_style {
+            IndicatorStyle::Classify => out.extend(indicator(&metadata(entry, options)?)),
+            IndicatorStyle::Slash if metadata(entry, options)?.is_dir() => out.push('/'),
+            _ => {}
+        }
+
+        if options.mark_mounts && entry.file_type()?.is_dir() && is_mount_point(&path) {
+            write!(out, " [mount]")?;
+        }
+
+        if options.symlink_chain && entry.file_type()?.is_symlink() {
+            let (hops, end) = resolve_chain(&path);
+            for hop in hops {
+                write!(out, " -> {}", hop.display())?;
+            }
+            match end {
+                ChainEnd::Target => {}
+                ChainEnd::Broken => write!(out, " [broken]")?,
+                ChainEnd::Cycle => write!(out, " [cycle]")?,
+                ChainEnd::TooDeep => write!(out, " [too many levels]")?,
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Breaks `line` into pieces no wider than `width` for `--wrap-names`,
+    /// each continuation indented by `indent` columns so the name lines up
+    /// (unless that leaves no room). Color sequences and zero-width
+    /// characters such as combining marks stay with the character before
+    /// them, so a base character and its accents are never split. A width
+    /// of 0 never wraps.
+    fn wrap_line(line: &str, width: usize, indent: usize) -> Vec<String> {
+        if width == 0 || display_width(line) <= width {
+            return vec![line.to_string()];
+        }
+        let indent = if indent < width / 2 { indent } else { 0 };
+        let mut pieces = Vec::new();
+        let mut piece = String::new();
+        let mut used = 0;
+        let mut chars = line.chars();
+        while let Some(c) = chars.next() {
+            if c == '\x1b' {
+                // An SGR sequence takes no room; keep it whole.
+                piece.push(c);
+                for c in chars.by_ref() {
+                    piece.push(c);
+                    if c == 'm' {
+                        break;
+                    }
+                }
+                continue;
+            }
+            let char_width = UnicodeWidthChar::width(c).unwrap_or(0);
+            if char_width > 0 && used + char_width > width && used > indent {
+                pieces.push(std::mem::take(&mut piece));
+                piece.extend(std::iter::repeat_n(' ', indent));
+                used = indent;
+            }
+            piece.push(c);
+            used += char_width;
+        }
+        pieces.push(piece);
+        pieces
+    }
+
+    /// Cuts a plain (uncolored) cell down to `width` columns, ending in `…`.
+    fn truncate(cell: &str, width: usize) -> String {
+        if width == 0 {
+            return String::new();
+        }
+        let mut out = String::new();
+        let mut used = 0;
+        for c in cell.chars() {
+            let char_width = UnicodeWidthChar::width(c).unwrap_or(0);
+            if used + char_width > width - 1 {
+                break;
+            }
+            out.push(c);
+            used += char_width;
+        }
+        out.push('…');
+        out
+    }
+
+    /// `-F` indicator for an entry: its type, or `*` for an executable file.
+    /// Plain files get none, as in GNU ls.
+    fn indicator(metadata: &Metadata) -> Option<char> {
+        let file_type = metadata.file_type();
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+            if file_type.is_fifo() {
+                return Some('|');
+            }
+            if file_type.is_socket() {
+                return Some('=');
+            }
+            if file_type.is_file() && metadata.permissions().mode() & 0o111 != 0 {
+                return Some('*');
+            }
+        }
+        match file_type {
+            t if t.is_dir() => Some('/'),
+            t if t.is_symlink() => Some('@'),
+            _ => None,
+        }
+    }
+
+    /// An entry's metadata as displayed: its own, or with `-L` its target's.
+    /// A broken link has no target and keeps describing itself.
+    fn metadata(entry: &DirEntry, options: &ListOptions) -> io::Result<Metadata> {
+        if options.dereference {
+            if let Ok(target) = fs::metadata(entry.path()) {
+                return Ok(target);
+            }
+        }
+        entry.metadata()
+    }
+
+    /// Half of an average Gregorian year, GNU's cut-off for recent times.
+    const SIX_MONTHS: i64 = 31_556_952 / 2;
+
+    fn render_time(
+        entry: &DirEntry,
+        options: &ListOptions,
+    ) -> Result<Option<String>, Box<dyn Error>> {
+        if let Some(time) = options.time {
+            let metadata = metadata(entry, options)?;
+            // Shown in local time, as GNU ls does and as `--at` is read.
+            let time: DateTime<Local> = crate::sort::metadata_time(&metadata, time)
+                .ok_or("this time is not available on this platform")?
+                .into();
+            let time_string = match options.relative_to {
+                Some(now) => humanize_duration(
+                    now.timestamp() - time.timestamp(),
+                    options.relative_granularity,
+                ),
+                None => match &options.time_style {
+                    Some((style, now)) => {
+                        let age = now.timestamp() - time.timestamp();
+                        let recent = (0..SIX_MONTHS).contains(&age);
+                        time.format(if recent { &style.recent } else { &style.old })
+                            .to_string()
+                    }
+                    None => time.format("%b %e %R").to_string(),
+                },
+            };
+            return Ok(Some(time_string));
+        }
+
+        Ok(None)
+    }
+
+    /// A directory is a mount point when it lives on a different device
+    /// than its parent.
+    #[cfg(unix)]
+    fn is_mount_point(path: &Path) -> bool {
+        use std::os::unix::fs::MetadataExt;
+        let parent = match path.parent() {
+            Some(parent) if parent.as_os_str().is_empty() => Path::new("."),
+            Some(parent) => parent,
+            None => return false,
+        };
+        match (fs::symlink_metadata(path), fs::metadata(parent)) {
+            (Ok(dir), Ok(parent)) => dir.dev() != parent.dev(),
+            _ => false,
+        }
+    }
+
+    #[cfg(not(unix))]
+    fn is_mount_point(_path: &Path) -> bool {
+        false
+    }
+
+    /// Device ID of the filesystem holding the entry, as `major:minor` on
+    /// Linux and the raw `st_dev` elsewhere on Unix.
+    #[cfg(target_os = "linux")]
+    fn device_id(metadata: &fs::Metadata) -> String {
+        use std::os::unix::fs::MetadataExt;
+        // Linux's dev_t encoding, as decoded by glibc's major()/minor().
+        let dev = metadata.dev();
+        let major = ((dev >> 8) & 0xfff) | ((dev >> 32) & !0xfff);
+        let minor = (dev & 0xff) | ((dev >> 12) & !0xff);
+        format!("{}:{}", major, minor)
+    }
+
+    #[cfg(all(unix, not(target_os = "linux")))]
+    fn device_id(metadata: &fs::Metadata) -> String {
+        use std::os::unix::fs::MetadataExt;
+        metadata.dev().to_string()
+    }
+
+    #[cfg(not(unix))]
+    fn device_id(_metadata: &fs::Metadata) -> String {
+        "-".to_string()
+    }
+
+    /// Unit of the `-s` column, as in coreutils.
+    const BLOCK_SIZE: u64 = 1024;
+
+    /// Bytes allocated on disk.
+    #[cfg(unix)]
+    fn allocated_bytes(metadata: &fs::Metadata) -> u64 {
+        use std::os::unix::fs::MetadataExt;
+        // `st_blocks` is always in 512-byte units.
+        metadata.blocks() * 512
+    }
+
+    #[cfg(not(unix))]
+    fn allocated_bytes(metadata: &fs::Metadata) -> u64 {
+        metadata.len().next_multiple_of(BLOCK_SIZE)
+    }
+
+    /// Follows `path` one `read_link` at a time, returning each hop as
+    /// written in the link, until a non-symlink, a missing target, a cycle
+    /// or `MAX_SYMLINK_HOPS` is reached.
+    pub fn resolve_chain(path: &Path) -> (Vec<PathBuf>, ChainEnd) {
+        let mut hops = Vec::new();
+        let mut seen = HashSet::new();
+        let mut current = path.to_path_buf();
+        seen.insert(current.clone());
+
+        loop {
+            if hops.len() >= MAX_SYMLINK_HOPS {
+                return (hops, ChainEnd::TooDeep);
+            }
+            let target = match fs::read_link(&current) {
+                Ok(target) => target,
+                Err(_) => return (hops, ChainEnd::Target),
+            };
+            let next = current
+                .parent()
+                .unwrap_or_else(|| Path::new(""))
+                .join(&target);
+            hops.push(target);
+
+            if !seen.insert(next.clone()) {
+                return (hops, ChainEnd::Cycle);
+            }
+            match fs::symlink_metadata(&next) {
+                Err(_) => return (hops, ChainEnd::Broken),
+                Ok(metadata) if !metadata.file_type().is_symlink() => {
+                    return (hops, ChainEnd::Target)
+                }
+                Ok(_) => current = next,
+            }
+        }
+    }
+
+    /// Renders a signed number of seconds from the coarsest unit that fits
+    /// down to `granularity`, skipping zero parts: `"3 days 4 hours ago"` or
+    /// `"in 2 hours"`. Without a granularity only the coarsest unit is
+    /// shown, and the first unit is always shown even if finer than asked.
+    pub fn humanize_duration(seconds: i64, granularity: Option<TimeUnit>) -> String {
+        let magnitude = seconds.abs();
+        if magnitude == 0 {
+            return "just now".to_string();
+        }
+
+        let first = TimeUnit::ALL
+            .into_iter()
+            .find(|unit| magnitude >= unit.seconds())
+            .unwrap_or(TimeUnit::Second);
+        let last = granularity.filter(|&unit| unit > first).unwrap_or(first);
+
+        let mut rest = magnitude;
+        let mut parts = Vec::new();
+        for unit in TimeUnit::ALL
+            .into_iter()
+            .filter(|&u| u >= first && u <= last)
+        {
+            let count = rest / unit.seconds();
+            rest %= unit.seconds();
+            if count > 0 {
+                let plural = if count == 1 { "" } else { "s" };
+                parts.push(format!("{} {}{}", count, unit.name(), plural));
+            }
+        }
+        let parts = parts.join(" ");
+
+        if seconds > 0 {
+            format!("{} ago", parts)
+        } else {
+            format!("in {}", parts)
+        }
+    }
+
+    /// Makes terminal escape sequences in a name visible instead of letting
+    /// the terminal interpret them: CSI (`ESC [` or U+009B) and OSC (`ESC ]`)
+    /// sequences are spelled out with a literal `\e`, e.g. `\e[31m`.
+    pub fn strip_ansi(name: &str) -> String {
+        let mut out = String::with_capacity(name.len());
+        let mut chars = name.chars().peekable();
+
+        while let Some(c) = chars.next() {
+            match c {
+                '\x1b' if chars.peek() == Some(&'[') => {
+                    chars.next();
+                    out.push_str("\\e[");
+                    push_csi_body(&mut out, &mut chars);
+                }
+                '\u{9b}' => {
+                    out.push_str("\\e[");
+                    push_csi_body(&mut out, &mut chars);
+                }
+                '\x1b' if chars.peek() == Some(&']') => {
+                    chars.next();
+                    out.push_str("\\e]");
+                    // OSC runs until BEL or the string terminator `ESC \`.
+                    while let Some(c) = chars.next() {
+                        match c {
+                            '\x07' => {
+                                out.push_str("\\a");
+                                break;
+                            }
+                            '\x1b' if chars.peek() == Some(&'\\') => {
+                                chars.next();
+                                out.push_str("\\e\\");
+                                break;
+                            }
+                            '\x1b' => out.push_str("\\e"),
+                            c => out.push(c),
+                        }
+                    }
+                }
+                '\x1b' => out.push_str("\\e"),
+                c => out.push(c),
+            }
+        }
+        out
+    }
+
+    /// Copies CSI parameter bytes up to and including the final byte.
+    fn push_csi_body(out: &mut String, chars: &mut std::iter::Peekable<std::str::Chars>) {
+        for c in chars.by_ref() {
+            if c == '\x1b' {
+                out.push_str("\\e");
+                continue;
+            }
+            out.push(c);
+            if ('@'..='~').contains(&c) {
+                break;
+            }
+        }
+    }
+
+    /// Replaces control characters with `?`, as `ls -q` does.
+    fn hide_control(s: &str) -> String {
+        s.chars()
+            .map(|c| if c.is_control() { '?' } else { c })
+            .collect()
+    }
+
+    /// `-b` escaping. Printable characters pass through; a backslash is
+    /// doubled; every other byte, including each byte of a non-printable
+    /// or invalid UTF-8 sequence, becomes `\NNN` octal or `\xNN` hex. The
+    /// result decodes back exactly with `unescape_name`.
+    fn escape_bytes(bytes: &[u8], style: EscapeStyle) -> String {
+        let mut escaped = String::new();
+        let escape = |escaped: &mut String, byte: u8| match style {
+            EscapeStyle::Octal => write!(escaped, "\\{:03o}", byte).unwrap(),
+            EscapeStyle::Hex => write!(escaped, "\\x{:02x}", byte).unwrap(),
+        };
+        for chunk in bytes.utf8_chunks() {
+            for c in chunk.valid().chars() {
+                if c == '\\' {
+                    escaped.push_str("\\\\");
+                } else if c.is_ascii_graphic() || (!c.is_ascii() && !c.is_control()) {
+                    escaped.push(c);
+                } else {
+                    let mut buf = [0; 4];
+                    for byte in c.encode_utf8(&mut buf).bytes() {
+                        escape(&mut escaped, byte);
+                    }
+                }
+            }
+            for &byte in chunk.invalid() {
+                escape(&mut escaped, byte);
+            }
+        }
+        escaped
+    }
+
+    /// Inverse of `escape_bytes`: decodes `\\`, `\NNN` octal and `\xNN` hex
+    /// escapes.
+    pub fn unescape_name(s: &str) -> Result<Vec<u8>, String> {
+        let mut bytes = Vec::with_capacity(s.len());
+        let mut rest = s.as_bytes();
+        while let Some((&byte, tail)) = rest.split_first() {
+            rest = tail;
+            if byte != b'\\' {
+                bytes.push(byte);
+                continue;
+            }
+            match rest {
+                [b'\\', tail @ ..] => {
+                    bytes.push(b'\\');
+                    rest = tail;
+                }
+                [a @ b'0'..=b'3', b @ b'0'..=b'7', c @ b'0'..=b'7', tail @ ..] => {
+                    bytes.push((a - b'0') << 6 | (b - b'0') << 3 | (c - b'0'));
+                    rest = tail;
+                }
+                [b'x', hi, lo, tail @ ..] if hi.is_ascii_hexdigit() && lo.is_ascii_hexdigit() => {
+                    let digits = [*hi, *lo];
+                    let hex = std::str::from_utf8(&digits).unwrap();
+                    bytes.push(u8::from_str_radix(hex, 16).unwrap());
+                    rest = tail;
+                }
+                _ => return Err(format!("invalid escape in '{}'", s)),
+            }
+        }
+        Ok(bytes)
+    }
+
+    /// `--unescape`: decodes each `eol`-terminated record of `input` with
+    /// `unescape_name` and writes the raw bytes to `output`, stopping at the
+    /// first malformed record.
+    pub fn unescape_stream(
+        input: impl io::BufRead,
+        mut output: impl io::Write,
+        eol: u8,
+    ) -> Result<(), Box<dyn Error>> {
+        for (index, record) in input.split(eol).enumerate() {
+            let record = record?;
+            let decoded = std::str::from_utf8(&record)
+                .map_err(|_| "not valid UTF-8".to_string())
+                .and_then(unescape_name)
+                .map_err(|err| format!("--unescape: record {}: {}", index + 1, err))?;
+            output.write_all(&decoded)?;
+            output.write_all(&[eol])?;
+        }
+        output.flush()?;
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    fn os_bytes(name: &OsStr) -> Cow<'_, [u8]> {
+        use std::os::unix::ffi::OsStrExt;
+        Cow::Borrowed(name.as_bytes())
+    }
+
+    #[cfg(not(unix))]
+    fn os_bytes(name: &OsStr) -> Cow<'_, [u8]> {
+        match name.to_string_lossy() {
+            Cow::Borrowed(s) => Cow::Borrowed(s.as_bytes()),
+            Cow::Owned(s) => Cow::Owned(s.into_bytes()),
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::compute_columns;
+
+        #[test]
+        fn test_compute_columns_exact_fit() {
+            // Three 3-wide names with 2-wide gaps make a 13-column line,
+            // which needs a 14-column terminal, as in GNU ls.
+            let layout = compute_columns(&[3, 3, 3], 14, 2, true);
+            assert_eq!(layout.columns(), 3);
+            assert_eq!(layout.widths, [5, 5, 3]);
+        }
+
+        #[test]
+        fn test_compute_columns_one_over() {
+            assert_eq!(compute_columns(&[3, 3, 3], 13, 2, true).columns(), 2);
+            assert_eq!(compute_columns(&[3, 3, 3], 13, 2, false).columns(), 2);
+        }
+
+        #[test]
+        fn test_compute_columns_wide_entry() {
+            // Nothing fits beside a name wider than the terminal.
+            assert_eq!(compute_columns(&[30, 1, 1], 20, 2, true).columns(), 1);
+            // Filling across, it only widens the first column.
+            assert_eq!(compute_columns(&[8, 1, 1, 1], 14, 2, false).columns(), 2);
+        }
+
+        #[test]
+        fn test_compute_columns_unlimited_width() {
+            assert_eq!(compute_columns(&[3, 3, 3], 0, 2, true).columns(), 3);
+            assert_eq!(compute_columns(&[], 80, 2, true).columns(), 1);
+        }
+    }
+}
+
+mod size {
+    const UNITS: [&str; 8] = ["K", "M", "G", "T", "P", "E", "Z", "Y"];
+
+    /// Formats `bytes` like GNU `ls -h`: powers of 1024, rounded up, with
+    /// one decimal below 10 (`1023`, `1.0K`, `1.1K`, `15M`). Display only;
+    /// anything that compares sizes must use the raw byte count.
+    pub fn human_size(bytes: u64) -> String {
+        if bytes < 1024 {
+            return bytes.to_string();
+        }
+
+        let mut value = bytes as f64 / 1024.0;
+        let mut unit = 0;
+        while value >= 1024.0 && unit < UNITS.len() - 1 {
+            value /= 1024.0;
+            unit += 1;
+        }
+
+        let mut rounded = if value < 10.0 {
+            (value * 10.0).ceil() / 10.0
+        } else {
+            value.ceil()
+        };
+        if rounded >= 1024.0 && unit < UNITS.len() - 1 {
+            unit += 1;
+            rounded = 1.0;
+        }
+
+        if rounded < 10.0 {
+            format!("{:.1}{}", rounded, UNITS[unit])
+        } else {
+            format!("{:.0}{}", rounded, UNITS[unit])
+        }
+    }
+
+    /// Renders `bytes` for a size column: a count of `unit`-byte units,
+    /// rounded up, or with `-h` humanized from the bytes themselves, so `-s`
+    /// and `--file-size` scale the same way.
+    pub fn scaled(bytes: u64, unit: u64, human: bool) -> String {
+        if human {
+            human_size(bytes)
+        } else {
+            bytes.div_ceil(unit).to_string()
+        }
+    }
+
+    /// Parses a size for `--larger-than`/`--smaller-than`; see
+    /// `parse_size_suffix`.
+    pub fn parse_size(s: &str) -> Result<u64, String> {
+        parse_size_suffix(s).ok_or_else(|| {
+            format!(
+                "invalid size '{}': expected a number with an optional suffix such as K, M, G or MB",
+                s
+            )
+        })
+    }
+
+    /// Parses a size such as `512`, `10K`, `1.5M` or `2GB`; suffixes are
+    /// case-insensitive. Bare suffixes and `KiB`-style ones are powers of
+    /// 1024; `KB`-style ones are powers of 1000, as in GNU `--block-size`.
+    /// A bare number must be a whole count of bytes. Returns `None` for
+    /// anything else, including sizes that do not fit in a `u64`.
+    pub fn parse_size_suffix(s: &str) -> Option<u64> {
+        let split = s
+            .find(|c: char| !c.is_ascii_digit() && c != '.')
+            .unwrap_or(s.len());
+        let (number, suffix) = s.split_at(split);
+        if number.is_empty() {
+            return None;
+        }
+
+        let (unit, base) = match suffix.len() {
+            0 => return number.parse().ok(),
+            1 => (suffix, 1024),
+            // `get` also rejects a multi-byte first character.
+            2 if suffix.get(1..).is_some_and(|b| b.eq_ignore_ascii_case("B")) => {
+                (&suffix[..1], 1000)
+            }
+            3 if suffix
+                .get(1..)
+                .is_some_and(|ib| ib.eq_ignore_ascii_case("iB")) =>
+            {
+                (&suffix[..1], 1024)
+            }
+            _ => return None,
+        };
+        let power = UNITS.iter().position(|u| u.eq_ignore_ascii_case(unit))? as u32 + 1;
+        let multiplier = u64::checked_pow(base, power)?;
+
+        // Whole numbers are scaled exactly; f64 would lose precision.
+        if let Ok(whole) = number.parse::<u64>() {
+            return whole.checked_mul(multiplier);
+        }
+        let bytes = number.parse::<f64>().ok()? * multiplier as f64;
+        if bytes >= u64::MAX as f64 {
+            return None;
+        }
+        Some(bytes as u64)
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::parse_size_suffix;
+
+        #[test]
+        fn test_parse_size_suffix() {
+            let valid: &[(&str, u64)] = &[
+                ("0", 0),
+                ("512", 512),
+                ("10K", 10 * 1024),
+                ("10k", 10 * 1024),
+                ("0.5K", 512),
+                ("1.5M", 1536 * 1024),
+                ("2MB", 2_000_000),
+                ("2mb", 2_000_000),
+                ("1KiB", 1024),
+                ("1kib", 1024),
+                ("3G", 3 << 30),
+                ("1.5GB", 1_500_000_000),
+                ("15E", 15 << 60),
+            ];
+            for &(input, bytes) in valid {
+                assert_eq!(parse_size_suffix(input), Some(bytes), "{}", input);
+            }
+        }
+
+        #[test]
+        fn test_parse_size_suffix_rejects_invalid() {
+            for input in [
+                "", "K", "abc", "-5", "1.2.3", "1.5", "10X", "10Q", "10KBB", "10Kb2", "1 K", "1é",
+            ] {
+                assert_eq!(parse_size_suffix(input), None, "{:?}", input);
+            }
+        }
+
+        #[test]
+        fn test_parse_size_suffix_overflow() {
+            for input in [
+                "16E",
+                "1Z",
+                "1Y",
+                "18446744073709551616",
+                "99999999999999999999",
+            ] {
+                assert_eq!(parse_size_suffix(input), None, "{}", input);
+            }
+            assert_eq!(parse_size_suffix("18446744073709551615"), Some(u64::MAX));
+        }
+    }
+}
+
+mod summary {
+    use std::collections::HashSet;
+    use std::error::Error;
+    use std::fs::{self, Metadata};
+    use std::path::{Path, PathBuf};
+
+    use crate::entries::DirEntry;
+    use crate::size;
+
+    #[derive(Default)]
+    struct Bucket {
+        count: usize,
+        size: u64,
+    }
+
+    /// Identity of a multiply-linked inode, so its size is only counted once.
+    #[cfg(unix)]
+    pub fn hard_link_key(metadata: &Metadata) -> Option<(u64, u64)> {
+        use std::os::unix::fs::MetadataExt;
+        if metadata.nlink() > 1 && !metadata.is_dir() {
+            Some((metadata.dev(), metadata.ino()))
+        } else {
+            None
+        }
+    }
+
+    #[cfg(not(unix))]
+    pub fn hard_link_key(_metadata: &Metadata) -> Option<(u64, u64)> {
+        None
+    }
+
+    /// Running `--size-by-type` totals. Entries are added one directory
+    /// group at a time, so only the hard-link identities seen so far are
+    /// kept, never the entries themselves.
+    #[derive(Default)]
+    pub struct SizeByType {
+        count_links: bool,
+        seen: HashSet<(u64, u64)>,
+        directories: Bucket,
+        files: Bucket,
+        symlinks: Bucket,
+        other: Bucket,
+    }
+
+    impl SizeByType {
+        pub fn new(count_links: bool) -> SizeByType {
+            SizeByType {
+                count_links,
+                ..SizeByType::default()
+            }
+        }
+
+        pub fn add(&mut self, entries: &[DirEntry]) -> Result<(), Box<dyn Error>> {
+            for entry in entries {
+                // `DirEntry::metadata` does not traverse symlinks, so links are
+                // counted with their own size rather than their target's.
+                let metadata = entry.metadata()?;
+                let file_type = metadata.file_type();
+                let bucket = if file_type.is_dir() {
+                    &mut self.directories
+                } else if file_type.is_symlink() {
+                    &mut self.symlinks
+                } else if file_type.is_file() {
+                    &mut self.files
+                } else {
+                    &mut self.other
+                };
+                bucket.count += 1;
+                let counted = match hard_link_key(&metadata) {
+                    Some(key) if !self.count_links => self.seen.insert(key),
+                    _ => true,
+                };
+                if counted {
+                    bucket.size += metadata.len();
+                }
+            }
+            Ok(())
+        }
+
+        pub fn print(self, human: bool) {
+            let rows = [
+                ("directories", self.directories),
+                ("files", self.files),
+                ("symlinks", self.symlinks),
+                ("other", self.other),
+            ];
+            let total: u64 = rows.iter().map(|(_, bucket)| bucket.size).sum();
+            let count: usize = rows.iter().map(|(_, bucket)| bucket.count).sum();
+
+            println!();
+            let format_size = |bytes: u64| {
+                if human {
+                    size::human_size(bytes)
+                } else {
+                    bytes.to_string()
+                }
+            };
+            println!("{:<12} {:>8} {:>12}", "type", "count", "size");
+            for (label, bucket) in rows.iter().filter(|(_, bucket)| bucket.count > 0) {
+                println!(
+                    "{:<12} {:>8} {:>12}",
+                    label,
+                    bucket.count,
+                    format_size(bucket.size)
+                );
+            }
+            println!("{:<12} {:>8} {:>12}", "total", count, format_size(total));
+        }
+    }
+
+    /// Prints `--rollup`: every directory down to `max_depth` (the root is
+    /// depth 0) with the total size of everything beneath it, largest first,
+    /// like `du --apparent-size --max-depth=N | sort -rh`. Sizes count files
+    /// and symlinks as links, the length of their target path, as `du` does
+    /// by default; directories' own sizes are left out. With `follow`, as
+    /// `du -L`, symlinks count as what they point to and linked directories
+    /// are descended into, each directory only once so cycles and repeated
+    /// links cannot inflate the totals. Hard-linked files count once, where
+    /// they are first met. Entries that cannot be read are reported and
+    /// skipped; the result says whether any were.
+    pub fn print_rollup(
+        root: &Path,
+        max_depth: Option<usize>,
+        human: bool,
+        follow: bool,
+    ) -> Result<bool, Box<dyn Error>> {
+        let mut rows = Vec::new();
+        let mut walk = Rollup {
+            max_depth: max_depth.unwrap_or(usize::MAX),
+            follow,
+            seen: HashSet::new(),
+            linked: HashSet::new(),
+            skipped: false,
+            rows: &mut rows,
+        };
+        if follow {
+            walk.first_visit(&fs::metadata(root)?);
+        }
+        walk.rollup(root, 0)?;
+        let skipped = walk.skipped;
+        rows.sort_by(|(a_size, a_path), (b_size, b_path)| {
+            b_size.cmp(a_size).then_with(|| a_path.cmp(b_path))
+        });
+
+        for (bytes, path) in rows {
+            let bytes = if human {
+                size::human_size(bytes)
+            } else {
+                bytes.to_string()
+            };
+            println!("{}\t{}", bytes, path.display());
+        }
+        Ok(skipped)
+    }
+
+    /// State of one `--rollup` walk.
+    struct Rollup<'a> {
+        max_depth: usize,
+        follow: bool,
+        /// Directories already counted, when following symlinks.
+        seen: HashSet<(u64, u64)>,
+        /// Hard-linked files already counted.
+        linked: HashSet<(u64, u64)>,
+        /// Whether anything could not be read.
+        skipped: bool,
+        rows: &'a mut Vec<(u64, PathBuf)>,
+    }
+
+    impl Rollup<'_> {
+        /// Total size beneath `dir`, recording it in `rows` when in depth.
+        fn rollup(&mut self, dir: &Path, depth: usize) -> Result<u64, Box<dyn Error>> {
+            let mut total = 0;
+            let read = match fs::read_dir(dir) {
+                Ok(read) => read,
+                // A subdirectory we cannot read counts as empty, as in du.
+                Err(err) if depth > 0 => {
+                    eprintln!("lsr: cannot read directory '{}': {}", dir.display(), err);
+                    self.skipped = true;
+                    return Ok(0);
+                }
+                Err(err) => return Err(err.into()),
+            };
+            for entry in read {
+                let entry = match entry {
+                    Ok(entry) => entry,
+                    Err(err) => {
+                        eprintln!("lsr: cannot read directory '{}': {}", dir.display(), err);
+                        self.skipped = true;
+                        continue;
+                    }
+                };
+                let mut metadata = match entry.metadata() {
+                    Ok(metadata) => metadata,
+                    Err(err) => {
+                        eprintln!("lsr: cannot access '{}': {}", entry.path().display(), err);
+                        self.skipped = true;
+                        continue;
+                    }
+                };
+                if self.follow && metadata.file_type().is_symlink() {
+                    // A dangling link still counts as itself.
+                    if let Ok(target) = fs::metadata(entry.path()) {
+                        metadata = target;
+                    }
+                }
+                if metadata.is_dir() {
+                    if self.follow && !self.first_visit(&metadata) {
+                        continue;
+                    }
+                    total += self.rollup(&entry.path(), depth + 1)?;
+                } else if hard_link_key(&metadata).is_none_or(|key| self.linked.insert(key)) {
+                    total += metadata.len();
+                }
+            }
+            if depth <= self.max_depth {
+                self.rows.push((total, dir.to_path_buf()));
+            }
+            Ok(total)
+        }
+
+        #[cfg(unix)]
+        fn first_visit(&mut self, metadata: &Metadata) -> bool {
+            use std::os::unix::fs::MetadataExt;
+            self.seen.insert((metadata.dev(), metadata.ino()))
+        }
+
+        #[cfg(not(unix))]
+        fn first_visit(&mut self, _metadata: &Metadata) -> bool {
+            true
+        }
+    }
+}
+
+mod exec {
+    use std::error::Error;
+    use std::io::{self, BufRead, Write};
+    use std::path::PathBuf;
+    use std::process::Command;
+
+    use crate::entries::DirEntry;
+
+    pub fn exec_entries(
+        command: &str,
+        entries: &[DirEntry],
+        confirm: bool,
+    ) -> Result<(), Box<dyn Error>> {
+        let mut words: Vec<&str> = command.split_whitespace().collect();
+        let batch = words.last() == Some(&"+");
+        if batch {
+            words.pop();
+        }
+        if words.is_empty() {
+            return Err("--exec requires a command".into());
+        }
+
+        let paths: Vec<PathBuf> = entries.iter().map(|entry| entry.path()).collect();
+        let invocations: Vec<Vec<String>> = if batch {
+            vec![substitute(&words, &paths)]
+        } else {
+            paths
+                .iter()
+                .map(|path| substitute(&words, std::slice::from_ref(path)))
+                .collect()
+        };
+
+        let mut failures = 0;
+        for argv in invocations {
+            if confirm && !ask(&argv)? {
+                continue;
+            }
+            let status = Command::new(&argv[0]).args(&argv[1..]).status();
+            match status {
+                Ok(status) if status.success() => {}
+                Ok(_) => failures += 1,
+                Err(err) => {
+                    eprintln!("lsr: {}: {}", argv[0], err);
+                    failures += 1;
+                }
+            }
+        }
+
+        if failures > 0 {
+            return Err(format!("--exec: {} invocation(s) failed", failures).into());
+        }
+        Ok(())
+    }
+
+    /// Replaces `{}` with `paths`, also inside a longer word (`x{}y`), which
+    /// then yields one word per path; if there is none the paths are
+    /// appended, as `find -exec` users would expect.
+    fn substitute(words: &[&str], paths: &[PathBuf]) -> Vec<String> {
+        let paths: Vec<String> = paths
+            .iter()
+            .map(|path| path.to_string_lossy().into_owned())
+            .collect();
+        if !words.iter().any(|word| word.contains("{}")) {
+            return words.iter().map(|w| w.to_string()).chain(paths).collect();
+        }
+
+        let mut argv = Vec::new();
+        for word in words {
+            if word.contains("{}") {
+                argv.extend(paths.iter().map(|path| word.replace("{}", path)));
+            } else {
+                argv.push(word.to_string());
+            }
+        }
+        argv
+    }
+
+    fn ask(argv: &[String]) -> Result<bool, Box<dyn Error>> {
+        eprint!("< {} >? ", argv.join(" "));
+        io::stderr().flush()?;
+
+        let mut answer = String::new();
+        io::stdin().lock().read_line(&mut answer)?;
+        Ok(matches!(answer.trim(), "y" | "Y" | "yes"))
+    }
+}
+
+mod sort {
+    use std::cmp::{Ordering, Reverse};
+    use std::fs::{self, Metadata};
+    use std::time::SystemTime;
+
+    use crate::args::{SortField, TimeSort};
+    use crate::entries::DirEntry;
+    use crate::stat;
+
+    pub fn sort_entries(entries: &mut [DirEntry], field: SortField, time: TimeSort, reverse: bool) {
+        if field == SortField::None {
+            return;
+        }
+        if field == SortField::ChildCount {
+            // Counting reads each directory, so do it only once per entry.
+            entries.sort_by_cached_key(|entry| (Reverse(child_count(entry)), name(entry)));
+            if reverse {
+                entries.reverse();
+            }
+            return;
+        }
+
+        entries.sort_by(|a, b| {
+            let ordering = match field {
+                SortField::None | SortField::Name => Ordering::Equal,
+                // Largest and newest first, as in GNU ls. Sizes compare as raw
+                // byte counts, never as their (rounded) `-h` rendering.
+                SortField::Size => size(b).cmp(&size(a)),
+                SortField::Time => time_of(b, time).cmp(&time_of(a, time)),
+                SortField::Extension => extension(a).cmp(&extension(b)),
+                SortField::Version => version_cmp(&name(a), &name(b)),
+                SortField::Links => links(b).cmp(&links(a)),
+                SortField::ChildCount => unreachable!("sorted by cached key above"),
+            }
+            .then_with(|| name(a).cmp(&name(b)));
+
+            if reverse {
+                ordering.reverse()
+            } else {
+                ordering
+            }
+        });
+    }
+
+    /// Newest (or oldest) entry by mtime in a single pass; on ties the one
+    /// that sorts first by name wins.
+    pub fn extreme_by_mtime<I>(entries: I, newest: bool) -> Option<DirEntry>
+    where
+        I: IntoIterator<Item = DirEntry>,
+    {
+        entries
+            .into_iter()
+            .map(|entry| (time_of(&entry, TimeSort::Mtime), entry))
+            .reduce(|best, candidate| {
+                let ordering = candidate.0.cmp(&best.0);
+                let better = if newest {
+                    ordering == Ordering::Greater
+                } else {
+                    ordering == Ordering::Less
+                };
+                let tie_wins = ordering == Ordering::Equal && name(&candidate.1) < name(&best.1);
+                if better || tie_wins {
+                    candidate
+                } else {
+                    best
+                }
+            })
+            .map(|(_, entry)| entry)
+    }
+
+    fn name(entry: &DirEntry) -> String {
+        entry.file_name().to_string_lossy().into_owned()
+    }
+
+    fn size(entry: &DirEntry) -> u64 {
+        entry.metadata().map(|metadata| metadata.len()).unwrap_or(0)
+    }
+
+    /// Hard-link count; without one (non-Unix) every entry ties, leaving
+    /// name order.
+    #[cfg(unix)]
+    fn links(entry: &DirEntry) -> u64 {
+        use std::os::unix::fs::MetadataExt;
+        entry
+            .metadata()
+            .map(|metadata| metadata.nlink())
+            .unwrap_or(0)
+    }
+
+    #[cfg(not(unix))]
+    fn links(_entry: &DirEntry) -> u64 {
+        1
+    }
+
+    /// Number of entries in a directory (`.` and `..` aside), or `None` for
+    /// anything else so it sorts after every directory. Symlinks are not
+    /// followed; an unreadable directory counts as empty.
+    fn child_count(entry: &DirEntry) -> Option<usize> {
+        if !entry.file_type().is_ok_and(|t| t.is_dir()) {
+            return None;
+        }
+        Some(fs::read_dir(entry.path()).map_or(0, |read| read.count()))
+    }
+
+    fn time_of(entry: &DirEntry, time: TimeSort) -> Option<SystemTime> {
+        metadata_time(&entry.metadata().ok()?, time)
+    }
+
+    /// The timestamp `time` selects. On Unix ctime is the inode change time
+    /// (`st_ctime`), not the birth time `Metadata::created` reports; other
+    /// platforms have no change time and fall back to creation time.
+    pub fn metadata_time(metadata: &Metadata, time: TimeSort) -> Option<SystemTime> {
+        match time {
+            TimeSort::Atime => metadata.accessed().ok(),
+            TimeSort::Mtime => metadata.modified().ok(),
+            TimeSort::Ctime => stat::change_time(metadata).or_else(|| metadata.created().ok()),
+        }
+    }
+
+    /// Sort key for `-X`, as defined by `Path::extension`: the text after
+    /// the last dot, so `a.tar.gz` keys on `gz`, while a leading dot does not
+    /// start one (`.bashrc` and `Makefile` have none and sort first). Keys
+    /// compare case-insensitively.
+    fn extension(entry: &DirEntry) -> String {
+        entry
+            .path()
+            .extension()
+            .map(|ext| ext.to_string_lossy().to_lowercase())
+            .unwrap_or_default()
+    }
+
+    /// Compares names treating runs of digits as numbers, so `file2`
+    /// sorts before `file10`.
+    fn version_cmp(a: &str, b: &str) -> Ordering {
+        let mut a = a.chars().peekable();
+        let mut b = b.chars().peekable();
+
+        loop {
+            match (a.peek().copied(), b.peek().copied()) {
+                (None, None) => return Ordering::Equal,
+                (None, Some(_)) => return Ordering::Less,
+                (Some(_), None) => return Ordering::Greater,
+                (Some(x), Some(y)) if x.is_ascii_digit() && y.is_ascii_digit() => {
+                    let x = take_number(&mut a);
+                    let y = take_number(&mut b);
+                    let ordering = x
+                        .trim_start_matches('0')
+                        .len()
+                        .cmp(&y.trim_start_matches('0').len())
+                        .then_with(|| x.trim_start_matches('0').cmp(y.trim_start_matches('0')));
+                    if ordering != Ordering::Equal {
+                        return ordering;
+                    }
+                }
+                (Some(x), Some(y)) => {
+                    if x != y {
+                        return x.cmp(&y);
+                    }
+                    a.next();
+                    b.next();
+                }
+            }
+        }
+    }
+
+    fn take_number(chars: &mut std::iter::Peekable<std::str::Chars>) -> String {
+        let mut number = String::new();
+        while let Some(c) = chars.next_if(|c| c.is_ascii_digit()) {
+            number.push(c);
+        }
+        number
+    }
+}
+
+mod color {
+    use std::fs::{self, Metadata};
+    use std::path::Path;
+    use std::time::{Duration, SystemTime};
+
+    use crate::args::{ColorBy, ThemeName};
+
+    /// Palette for `--color-by=owner`; a uid always maps to the same slot.
+    const OWNER_PALETTE: [&str; 12] = [
+        "31", "32", "33", "34", "35", "36", "91", "92", "93", "94", "95", "96",
+    ];
+
+    /// `--color-by=age` buckets, brightest for the most recent changes.
+    const AGE_BUCKETS: [(Duration, &str); 4] = [
+        (Duration::from_secs(60 * 60), "01;32"),
+        (Duration::from_secs(24 * 60 * 60), "32"),
+        (Duration::from_secs(7 * 24 * 60 * 60), "33"),
+        (Duration::from_secs(30 * 24 * 60 * 60), "37"),
+    ];
+    const AGE_OLD: &str = "90";
+
+    /// Colors for `--color-by=type`, keyed like `dircolors`.
+    #[derive(Clone, Debug, PartialEq)]
+    pub struct Theme {
+        pub dir: String,
+        pub link: String,
+        pub orphan: String,
+        pub fifo: String,
+        pub socket: String,
+        pub device: String,
+        pub exec: String,
+        /// Directories that are world-writable (`ow`), sticky and
+        /// world-writable like `/tmp` (`tw`), or only sticky (`st`).
+        pub other_writable: String,
+        pub sticky_other_writable: String,
+        pub sticky: String,
+        /// `(suffix, code)` pairs for regular files, e.g. `(".rs", "33")`.
+        pub extensions: Vec<(String, String)>,
+    }
+
+    impl Theme {
+        pub fn builtin(name: ThemeName) -> Theme {
+            let codes = match name {
+                // GNU `dircolors` defaults.
+                ThemeName::Default => [
+                    "01;34", "01;36", "40;31;01", "40;33", "01;35", "40;33;01", "01;32",
+                ],
+                ThemeName::Dark => ["01;94", "01;96", "01;91", "93", "01;95", "01;93", "01;92"],
+                ThemeName::Light => ["34", "36", "01;31", "33", "35", "01;33", "32"],
+                ThemeName::Solarized => [
+                    "38;5;33", "38;5;37", "38;5;160", "38;5;136", "38;5;125", "38;5;166", "38;5;64",
+                ],
+            };
+            let [dir, link, orphan, fifo, socket, device, exec] = codes.map(String::from);
+            Theme {
+                dir,
+                link,
+                orphan,
+                fifo,
+                socket,
+                device,
+                exec,
+                // The GNU background highlights read on any palette.
+                other_writable: "34;42".to_string(),
+                sticky_other_writable: "30;42".to_string(),
+                sticky: "37;44".to_string(),
+                extensions: Vec::new(),
+            }
+        }
+
+        /// Applies `KEY=SGR` overrides separated by newlines or colons. Blank
+        /// lines and `#` comments are ignored; unknown keys and codes that are
+        /// not digits and semicolons are rejected.
+        pub fn apply(&mut self, spec: &str) -> Result<(), String> {
+            let pairs = spec
+                .lines()
+                .map(|line| line.split('#').next().unwrap_or("").trim())
+                .flat_map(|line| line.split(':'))
+                .map(str::trim)
+                .filter(|pair| !pair.is_empty());
+
+            for pair in pairs {
+                let (key, code) = pair
+                    .split_once('=')
+                    .ok_or_else(|| format!("invalid theme entry '{}': expected KEY=SGR", pair))?;
+                self.set(key, code)?;
+            }
+            Ok(())
+        }
+
+        /// Applies a `LS_COLORS` value. Unlike `apply`, entries this theme
+        /// has no slot for (`fi`, `su`, `mi`, ...) and malformed ones are
+        /// skipped, since the variable is written for GNU ls.
+        pub fn apply_ls_colors(&mut self, spec: &str) {
+            for pair in spec.split(':') {
+                if let Some((key, code)) = pair.split_once('=') {
+                    let _ = self.set(key, code);
+                }
+            }
+        }
+
+        fn set(&mut self, key: &str, code: &str) -> Result<(), String> {
+            if code.is_empty() || !code.chars().all(|c| c.is_ascii_digit() || c == ';') {
+                return Err(format!("invalid color '{}' for '{}'", code, key));
+            }
+            let code = code.to_string();
+            match key {
+                "di" => self.dir = code,
+                "ln" => self.link = code,
+                "or" => self.orphan = code,
+                "pi" => self.fifo = code,
+                "so" => self.socket = code,
+                "bd" | "cd" => self.device = code,
+                "ex" => self.exec = code,
+                "ow" => self.other_writable = code,
+                "tw" => self.sticky_other_writable = code,
+                "st" => self.sticky = code,
+                _ => match key.strip_prefix('*') {
+                    Some(suffix) if !suffix.is_empty() => {
+                        self.extensions.retain(|(known, _)| known != suffix);
+                        self.extensions.push((suffix.to_string(), code));
+                    }
+                    _ => return Err(format!("unknown theme key '{}'", key)),
+                },
+            }
+            Ok(())
+        }
+    }
+
+    pub fn paint(code: &str, text: &str) -> String {
+        format!("\x1b[{}m{}\x1b[0m", code, text)
+    }
+
+    /// SGR code for an entry under `scheme`, or `None` to leave it uncolored.
+    /// `metadata` describes a symlink itself unless `-L` is in effect.
+    /// With `smart_exec`, executable bits only earn the `ex` color when the
+    /// file also looks runnable; see `looks_executable`. Ages are measured
+    /// back from `now`.
+    pub fn color_for(
+        path: &Path,
+        metadata: &Metadata,
+        scheme: ColorBy,
+        theme: &Theme,
+        smart_exec: bool,
+        now: SystemTime,
+    ) -> Option<String> {
+        match scheme {
+            ColorBy::Type => by_type(path, metadata, theme, smart_exec).map(String::from),
+            ColorBy::Owner => by_owner(metadata),
+            ColorBy::Age => Some(by_age(metadata, now).to_string()),
+        }
+    }
+
+    fn by_type<'a>(
+        path: &Path,
+        metadata: &Metadata,
+        theme: &'a Theme,
+        smart_exec: bool,
+    ) -> Option<&'a str> {
+        let file_type = metadata.file_type();
+        if file_type.is_dir() {
+            return Some(dir_color(metadata, theme));
+        }
+        if file_type.is_symlink() {
+            return Some(if fs::metadata(path).is_ok() {
+                &theme.link
+            } else {
+                &theme.orphan
+            });
+        }
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+
+            if file_type.is_fifo() {
+                return Some(&theme.fifo);
+            }
+            if file_type.is_socket() {
+                return Some(&theme.socket);
+            }
+            if file_type.is_block_device() || file_type.is_char_device() {
+                return Some(&theme.device);
+            }
+            if metadata.permissions().mode() & 0o111 != 0
+                && (!smart_exec || !file_type.is_file() || looks_executable(path))
+            {
+                return Some(&theme.exec);
+            }
+        }
+
+        // As in GNU ls, suffixes only apply to plain, non-executable files.
+        let name = path.file_name()?.to_string_lossy();
+        theme
+            .extensions
+            .iter()
+            .rev()
+            .find(|(suffix, _)| name.ends_with(suffix.as_str()))
+            .map(|(_, code)| code.as_str())
+    }
+
+    /// `tw`, `ow` or `st` for directories with risky permission bits, as in
+    /// GNU ls; otherwise `di`.
+    #[cfg(unix)]
+    fn dir_color<'a>(metadata: &Metadata, theme: &'a Theme) -> &'a str {
+        use std::os::unix::fs::PermissionsExt;
+
+        let mode = metadata.permissions().mode();
+        match (mode & 0o1000 != 0, mode & 0o002 != 0) {
+            (true, true) => &theme.sticky_other_writable,
+            (false, true) => &theme.other_writable,
+            (true, false) => &theme.sticky,
+            (false, false) => &theme.dir,
+        }
+    }
+
+    #[cfg(not(unix))]
+    fn dir_color<'a>(_metadata: &Metadata, theme: &'a Theme) -> &'a str {
+        &theme.dir
+    }
+
+    /// Whether a file starts like something the system can run: a `#!`
+    /// script, or an ELF, PE (`MZ`) or Mach-O binary. Reads 4 bytes.
+    #[cfg(unix)]
+    fn looks_executable(path: &Path) -> bool {
+        use std::io::Read;
+
+        let mut magic = [0; 4];
+        let read = fs::File::open(path).and_then(|mut file| file.read(&mut magic));
+        let magic = match read {
+            Ok(n) => &magic[..n],
+            Err(_) => return false,
+        };
+        magic.starts_with(b"#!")
+            || magic.starts_with(b"MZ")
+            || magic == b"\x7fELF"
+            || matches!(
+                magic,
+                [0xfe, 0xed, 0xfa, 0xce | 0xcf]
+                    | [0xce | 0xcf, 0xfa, 0xed, 0xfe]
+                    | [0xca, 0xfe, 0xba, 0xbe]
+            )
+    }
+
+    #[cfg(unix)]
+    fn by_owner(metadata: &Metadata) -> Option<String> {
+        use std::os::unix::fs::MetadataExt;
+        let slot = metadata.uid() as usize % OWNER_PALETTE.len();
+        Some(OWNER_PALETTE[slot].to_string())
+    }
+
+    #[cfg(not(unix))]
+    fn by_owner(_metadata: &Metadata) -> Option<String> {
+        None
+    }
+
+    fn by_age(metadata: &Metadata, now: SystemTime) -> &'static str {
+        let age = match metadata.modified() {
+            Ok(modified) => now.duration_since(modified).unwrap_or_default(),
+            Err(_) => return AGE_OLD,
+        };
+        AGE_BUCKETS
+            .iter()
+            .find(|(limit, _)| age < *limit)
+            .map(|(_, code)| *code)
+            .unwrap_or(AGE_OLD)
+    }
+}
+
+mod stat {
+    use chrono::{DateTime, Local};
+    use std::error::Error;
+    use std::fs::{self, Metadata};
+    use std::path::Path;
+    use std::time::SystemTime;
+
+    /// Prints a labeled, `stat(1)`-like block for `path` (not followed if it
+    /// is a symlink).
+    pub fn print_stat(path: &Path) -> Result<(), Box<dyn Error>> {
+        let metadata = fs::symlink_metadata(path)?;
+
+        println!("  File: {}", path.display());
+        if metadata.file_type().is_symlink() {
+            println!("  Link: {}", fs::read_link(path)?.display());
+        }
+        println!("  Type: {}", file_type_name(&metadata));
+        print_platform(path, &metadata);
+        println!("Access: {}", format_time(metadata.accessed().ok()));
+        println!("Modify: {}", format_time(metadata.modified().ok()));
+        println!("Change: {}", format_time(change_time(&metadata)));
+        println!(" Birth: {}", format_time(metadata.created().ok()));
+
+        Ok(())
+    }
+
+    fn format_time(time: Option<SystemTime>) -> String {
+        match time {
+            Some(time) => DateTime::<Local>::from(time)
+                .format("%Y-%m-%d %H:%M:%S%.9f %z")
+                .to_string(),
+            None => "-".to_string(),
+        }
+    }
+
+    #[cfg(unix)]
+    fn file_type_name(metadata: &Metadata) -> &'static str {
+        use std::os::unix::fs::FileTypeExt;
+        let file_type = metadata.file_type();
+        if file_type.is_dir() {
+            "directory"
+        } else if file_type.is_symlink() {
+            "symbolic link"
+        } else if file_type.is_fifo() {
+            "fifo"
+        } else if file_type.is_socket() {
+            "socket"
+        } else if file_type.is_block_device() {
+            "block special file"
+        } else if file_type.is_char_device() {
+            "character special file"
+        } else if metadata.len() == 0 {
+            "regular empty file"
+        } else {
+            "regular file"
+        }
+    }
+
+    #[cfg(not(unix))]
+    fn file_type_name(metadata: &Metadata) -> &'static str {
+        let file_type = metadata.file_type();
+        if file_type.is_dir() {
+            "directory"
+        } else if file_type.is_symlink() {
+            "symbolic link"
+        } else {
+            "regular file"
+        }
+    }
+
+    /// Inode change time (`st_ctime`), which `std` does not expose directly.
+    #[cfg(unix)]
+    pub fn change_time(metadata: &Metadata) -> Option<SystemTime> {
+        use std::os::unix::fs::MetadataExt;
+        use std::time::Duration;
+        let nanos = Duration::from_nanos(metadata.ctime_nsec() as u64);
+        let secs = Duration::from_secs(metadata.ctime().unsigned_abs());
+        if metadata.ctime() >= 0 {
+            Some(SystemTime::UNIX_EPOCH + secs + nanos)
+        } else {
+            Some(SystemTime::UNIX_EPOCH - secs + nanos)
+        }
+    }
+
+    #[cfg(not(unix))]
+    pub fn change_time(_metadata: &Metadata) -> Option<SystemTime> {
+        None
+    }
+
+    #[cfg(unix)]
+    fn print_platform(path: &Path, metadata: &Metadata) {
+        use std::os::unix::fs::MetadataExt;
+        let mode = metadata.mode();
+        println!(
+            "  Size: {:<12} Blocks: {:<10} IO Block: {}",
+            metadata.len(),
+            metadata.blocks(),
+            metadata.blksize()
+        );
+        println!(
+            "Device: {:<12} Inode: {:<11} Links: {}",
+            metadata.dev(),
+            metadata.ino(),
+            metadata.nlink()
+        );
+        println!(
+            "  Mode: ({:04o}/{})  Uid: {:<6} Gid: {}",
+            mode & 0o7777,
+            mode_string(path, mode),
+            metadata.uid(),
+            metadata.gid()
+        );
+    }
+
+    #[cfg(not(unix))]
+    fn print_platform(_path: &Path, metadata: &Metadata) {
+        println!("  Size: {}", metadata.len());
+        println!(
+            "  Mode: {}",
+            if metadata.permissions().readonly() {
+                "read-only"
+            } else {
+                "read-write"
+            }
+        );
+    }
+
+    /// `permissions_string` followed by `+` when `path` has an extended ACL,
+    /// as `ls -l` shows it.
+    #[cfg(unix)]
+    pub fn mode_string(path: &Path, mode: u32) -> String {
+        let mut out = permissions_string(mode);
+        if has_acl(path) {
+            out.push('+');
+        }
+        out
+    }
+
+    /// Whether `path` (not followed) carries a POSIX ACL: an access ACL
+    /// beyond its mode bits, or a default ACL on a directory. Linux stores
+    /// these as `system.posix_acl_*` extended attributes.
+    #[cfg(target_os = "linux")]
+    pub fn has_acl(path: &Path) -> bool {
+        use std::ffi::CString;
+        use std::os::unix::ffi::OsStrExt;
+
+        let path = match CString::new(path.as_os_str().as_bytes()) {
+            Ok(path) => path,
+            Err(_) => return false,
+        };
+        [c"system.posix_acl_access", c"system.posix_acl_default"]
+            .iter()
+            .any(|name| {
+                // SAFETY: both strings are NUL-terminated; a null buffer of
+                // size 0 only asks for the attribute's length.
+                let len = unsafe {
+                    libc::lgetxattr(path.as_ptr(), name.as_ptr(), std::ptr::null_mut(), 0)
+                };
+                len > 0
+            })
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    pub fn has_acl(_path: &Path) -> bool {
+        false
+    }
+
+    /// `ls -l` style mode string, e.g. `drwxr-sr-t`.
+    #[cfg(unix)]
+    pub fn permissions_string(mode: u32) -> String {
+        // File type bits; the values are the same on every Unix.
+        let kind = match mode & 0o170000 {
+            0o040000 => 'd',
+            0o120000 => 'l',
+            0o010000 => 'p',
+            0o140000 => 's',
+            0o060000 => 'b',
+            0o020000 => 'c',
+            _ => '-',
+        };
+
+        // (read, write, execute bit, special bit, char when special+exec,
+        // char when special only)
+        let triads = [
+            (0o400, 0o200, 0o100, 0o4000, 's', 'S'),
+            (0o040, 0o020, 0o010, 0o2000, 's', 'S'),
+            (0o004, 0o002, 0o001, 0o1000, 't', 'T'),
+        ];
+
+        let mut out = String::with_capacity(10);
+        out.push(kind);
+        for (read, write, exec, special, both, only_special) in triads {
+            out.push(if mode & read != 0 { 'r' } else { '-' });
+            out.push(if mode & write != 0 { 'w' } else { '-' });
+            out.push(match (mode & exec != 0, mode & special != 0) {
+                (true, true) => both,
+                (false, true) => only_special,
+                (true, false) => 'x',
+                (false, false) => '-',
+            });
+        }
+        out
+    }
+}
+
+mod audit {
+    use std::error::Error;
+    use std::path::{Path, PathBuf};
+
+    use crate::entries::DirEntry;
+
+    /// Findings for `--audit-perms`, collected one directory group at a time.
+    #[derive(Default)]
+    pub struct Audit {
+        world_writable: Vec<(u32, PathBuf)>,
+        setuid: Vec<(u32, PathBuf)>,
+        setgid: Vec<(u32, PathBuf)>,
+        foreign_group_writable: Vec<(u32, PathBuf)>,
+        /// A setuid file anyone can rewrite: a strong sign of compromise.
+        critical: bool,
+    }
+
+    impl Audit {
+        #[cfg(unix)]
+        pub fn add(&mut self, entries: &[DirEntry]) -> Result<(), Box<dyn Error>> {
+            use std::os::unix::fs::MetadataExt;
+
+            // SAFETY: geteuid cannot fail and has no preconditions.
+            let euid = unsafe { libc::geteuid() };
+            for entry in entries {
+                let metadata = entry.metadata()?;
+                // A symlink's own mode is always 0777 and grants nothing.
+                if metadata.file_type().is_symlink() {
+                    continue;
+                }
+                let mode = metadata.mode();
+                let path = entry.path();
+                if mode & 0o002 != 0 {
+                    self.world_writable.push((mode, path.clone()));
+                }
+                if mode & 0o4000 != 0 {
+                    self.setuid.push((mode, path.clone()));
+                    if mode & 0o002 != 0 {
+                        self.critical = true;
+                        eprintln!("lsr: world-writable setuid file: {}", path.display());
+                    }
+                }
+                // On directories the bit means "inherit group", not setgid.
+                if mode & 0o2000 != 0 && !metadata.is_dir() {
+                    self.setgid.push((mode, path.clone()));
+                }
+                if mode & 0o020 != 0 && metadata.uid() != euid {
+                    self.foreign_group_writable.push((mode, path));
+                }
+            }
+            Ok(())
+        }
+
+        #[cfg(not(unix))]
+        pub fn add(&mut self, _entries: &[DirEntry]) -> Result<(), Box<dyn Error>> {
+            Err("--audit-perms needs Unix permission bits".into())
+        }
+
+        /// Prints the report, one section per non-empty category, and returns
+        /// whether a world-writable setuid file was found.
+        pub fn print(self) -> bool {
+            let sections = [
+                ("world-writable", &self.world_writable),
+                ("setuid", &self.setuid),
+                ("setgid", &self.setgid),
+                (
+                    "group-writable, owned by another user",
+                    &self.foreign_group_writable,
+                ),
+            ];
+            let mut any = false;
+            for (title, findings) in sections.iter().filter(|(_, findings)| !findings.is_empty()) {
+                if any {
+                    println!();
+                }
+                any = true;
+                println!("{} ({}):", title, findings.len());
+                for (mode, path) in findings.iter() {
+                    println!("  {} {}", permissions(path, *mode), path.display());
+                }
+            }
+            if !any {
+                println!("no findings");
+            }
+            self.critical
+        }
+    }
+
+    #[cfg(unix)]
+    fn permissions(path: &Path, mode: u32) -> String {
+        crate::stat::mode_string(path, mode)
+    }
+
+    #[cfg(not(unix))]
+    fn permissions(_path: &Path, mode: u32) -> String {
+        format!("{:o}", mode)
+    }
+}
+
+mod git {
+    use std::collections::HashSet;
+    use std::fs;
+    use std::path::{Path, PathBuf};
+    use std::process::Command;
+    use std::sync::OnceLock;
+
+    use crate::entries::DirEntry;
+
+    /// Paths `git status` reports for `--git-modified`, set once in `main`.
+    static CHANGED: OnceLock<HashSet<PathBuf>> = OnceLock::new();
+
+    /// Asks git which files under the repositories containing `paths` are
+    /// modified, staged or untracked, and turns on `--git-modified`
+    /// filtering. Fails if any path is outside a repository, or without git.
+    pub fn load(paths: &[PathBuf]) -> Result<(), String> {
+        let mut changed = HashSet::new();
+        for path in paths {
+            changed.extend(changed_paths(path)?);
+        }
+        let _ = CHANGED.set(changed);
+        Ok(())
+    }
+
+    /// Changed files in the repository containing `path`, with the
+    /// directories leading to them.
+    fn changed_paths(path: &Path) -> Result<HashSet<PathBuf>, String> {
+        let git = |args: &[&str]| {
+            let output = Command::new("git")
+                .arg("-C")
+                .arg(path)
+                .args(args)
+                .output()
+                .map_err(|err| format!("--git-modified: cannot run git: {}", err))?;
+            if !output.status.success() {
+                return Err(format!(
+                    "--git-modified: '{}' is not inside a git repository",
+                    path.display()
+                ));
+            }
+            Ok(output.stdout)
+        };
+
+        let top = git(&["rev-parse", "--show-toplevel"])?;
+        let top = String::from_utf8_lossy(&top);
+        let root = fs::canonicalize(top.trim_end_matches('\n')).map_err(|err| err.to_string())?;
+        let status = git(&["status", "--porcelain=v1", "-z", "--untracked-files=all"])?;
+
+        let mut changed = HashSet::new();
+        let mut records = status.split(|&byte| byte == 0);
+        while let Some(record) = records.next() {
+            if record.len() < 4 {
+                continue;
+            }
+            // "XY PATH"; renames and copies are followed by the old path.
+            if matches!(record[0], b'R' | b'C') {
+                records.next();
+            }
+            let file = root.join(String::from_utf8_lossy(&record[3..]).as_ref());
+            // Directories on the way stay visible so -R can reach the file.
+            changed.extend(
+                file.ancestors()
+                    .take_while(|ancestor| ancestor.starts_with(&root) && *ancestor != root)
+                    .map(Path::to_path_buf),
+            );
+        }
+        Ok(changed)
+    }
 
-mod entries {
+    /// Drops the clean entries of `dir` once `load` has run.
+    pub fn retain_changed(dir: &Path, entries: &mut Vec<DirEntry>) {
+        let Some(changed) = CHANGED.get() else {
+            return;
+        };
+        match fs::canonicalize(dir) {
+            Ok(dir) => entries.retain(|entry| changed.contains(&dir.join(entry.file_name()))),
+            Err(_) => entries.clear(),
+        }
+    }
+}
+
+mod walk {
+    use std::collections::HashSet;
     use std::error::Error;
+    use std::ffi::OsString;
+    use std::fmt;
     use std::fs;
-    use std::fs::DirEntry;
-    use std::path::Path;
+    use std::io;
+    use std::path::{Path, PathBuf};
+    use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+    use std::thread;
+
+    use crate::args::{Arguments, SortField, TimeSort};
+    use crate::entries::{self, DirEntry};
+    use crate::{git, sort};
 
-    pub fn read_entries(
+    /// Reads, filters and sorts one directory at `depth`, as it will be
+    /// displayed, along with the subdirectories `-R` should descend into.
+    fn read_dir(
         path: &Path,
-        show_almost_all: bool,
-        max_depth: Option<usize>,
-        limit: Option<usize>,
+        args: &Arguments,
+        depth: usize,
+    ) -> Result<(Vec<DirEntry>, Vec<PathBuf>), Box<dyn Error>> {
+        // A subdirectory, or one of its ancestors below the root, swapped
+        // for a symlink after its parent was listed must not lead the walk
+        // elsewhere, unless -L asked to follow links.
+        let nofollow = if args.dereference { 0 } else { depth };
+        let entries = entries::read_entries(path, nofollow)?;
+        let mut entries = filtered(path, entries, args, args.show_hidden, depth);
+        let links = link_filter(args);
+        if !links.is_active() {
+            order(&mut entries, args);
+            let subdirs = subdirectories(&entries, args, depth)?;
+            return Ok((entries, subdirs));
+        }
+
+        // Symlink filters would hide every directory, so descend first.
+        sort::sort_entries(
+            &mut entries,
+            args.sort_field,
+            args.time.unwrap_or(TimeSort::Mtime),
+            args.reverse,
+        );
+        let subdirs = subdirectories(&entries, args, depth)?;
+        entries.retain(|entry| {
+            links.matches(entry)
+                || (args.prune_empty && entry.file_type().is_ok_and(|t| t.is_dir()))
+        });
+        order(&mut entries, args);
+        Ok((entries, subdirs))
+    }
+
+    fn link_filter(args: &Arguments) -> entries::LinkFilter {
+        entries::LinkFilter {
+            symlinks_only: args.symlinks_only,
+            broken_only: args.broken_symlinks,
+        }
+    }
+
+    /// Applies the listing filters to `entries`, all of the directory `path`
+    /// found `depth` levels below the listing root.
+    fn filtered(
+        path: &Path,
+        entries: Vec<DirEntry>,
+        args: &Arguments,
+        show_hidden: bool,
+        depth: usize,
+    ) -> Vec<DirEntry> {
+        let ignore_rules = if args.no_ignore {
+            Vec::new()
+        } else {
+            ignore_patterns(path, depth)
+        };
+        let mut entries = entries::filter_entries(
+            entries,
+            show_hidden,
+            args.show_almost_all,
+            entries::AccessFilter {
+                readable: args.only_readable,
+                writable: args.only_writable,
+                executable: args.only_executable,
+            },
+            entries::ModifiedFilter {
+                within: args.modified_within,
+                before: args.modified_before,
+            },
+            entries::SizeFilter {
+                larger_than: args.larger_than,
+                smaller_than: args.smaller_than,
+            },
+            entries::ExcludeFilter {
+                patterns: &args.exclude,
+                ignore_rules: &ignore_rules,
+                owners: &args.exclude_owner,
+            },
+            !args.prune_empty,
+        );
+        if args.git_modified {
+            git::retain_changed(path, &mut entries);
+        }
+        entries
+    }
+
+    /// File each directory's listing is written to under `--split-output`.
+    const LISTING_FILE: &str = "_listing.txt";
+
+    /// Where `--split-output` writes the listing of `dir`, a directory under
+    /// the walk's `root`: `out` mirrors the tree, with the listing in
+    /// `LISTING_FILE` inside each directory's mirror (created as needed). If
+    /// an entry of `dir` has that name, whose mirror would collide with it,
+    /// `.1`, `.2`, ... is appended.
+    pub fn split_output_path(
+        out: &Path,
+        root: &Path,
+        dir: &Path,
+        entries: &[DirEntry],
+    ) -> Result<PathBuf, Box<dyn Error>> {
+        let mirror = out.join(dir.strip_prefix(root).unwrap_or(Path::new("")));
+        fs::create_dir_all(&mirror)
+            .map_err(|err| format!("cannot create directory '{}': {}", mirror.display(), err))?;
+
+        let taken: HashSet<OsString> = entries.iter().map(DirEntry::file_name).collect();
+        let mut name = OsString::from(LISTING_FILE);
+        let mut suffix = 0;
+        while taken.contains(&name) {
+            suffix += 1;
+            name = format!("{}.{}", LISTING_FILE, suffix).into();
+        }
+        Ok(mirror.join(name))
+    }
+
+    /// Per-directory ignore files, lowest precedence first: ripgrep's
+    /// `.ignore` and `.rgignore`, then lsr's own `.lsrignore`.
+    const IGNORE_FILES: [&str; 3] = [".ignore", ".rgignore", ".lsrignore"];
+
+    /// Glob rules from the ignore files in `dir` and its ancestors up to the
+    /// listing root, `depth` levels up, so a file's rules cover its
+    /// directory and everything below it within the listing, however the
+    /// root was spelled; nothing above the root applies. Rules come lowest
+    /// precedence first: farther directories before nearer ones and, within
+    /// a directory, in `IGNORE_FILES` order, so the last matching rule is
+    /// the one that applies. One rule per line, matched against entry
+    /// names; blank lines and `#` comments are skipped. Unreadable files
+    /// are ignored.
+    fn ignore_patterns(dir: &Path, depth: usize) -> Vec<String> {
+        let ancestors: Vec<&Path> = dir.ancestors().take(depth + 1).collect();
+        ancestors
+            .into_iter()
+            .rev()
+            .flat_map(|ancestor| IGNORE_FILES.map(|file| ancestor.join(file)))
+            .filter_map(|file| fs::read_to_string(file).ok())
+            .flat_map(|contents| {
+                contents
+                    .lines()
+                    .map(str::trim)
+                    .filter(|line| !line.is_empty() && !line.starts_with('#'))
+                    .map(String::from)
+                    .collect::<Vec<_>>()
+            })
+            .collect()
+    }
+
+    /// Sorts entries for display and applies `--limit`.
+    fn order(entries: &mut Vec<DirEntry>, args: &Arguments) {
+        sort::sort_entries(
+            entries,
+            args.sort_field,
+            args.time.unwrap_or(TimeSort::Mtime),
+            args.reverse,
+        );
+        if let Some(limit) = args.limit {
+            entries.truncate(limit);
+        }
+    }
+
+    /// Reads the entries named in a `--files-from` manifest. Paths are
+    /// NUL-separated if the file contains a NUL, else one per line. Missing
+    /// paths are reported and skipped, and make the exit status 1, as
+    /// missing operands do; the rest go through the usual
+    /// filters, sort and `--limit` as if they shared one directory.
+    pub fn read_manifest(
+        manifest: &Path,
+        args: &Arguments,
     ) -> Result<Vec<DirEntry>, Box<dyn Error>> {
-        let mut entries: Vec<DirEntry> = fs::read_dir(path)?
-            .filter_map(|res| res.ok())
-            .filter(|entry| {
-                if show_almost_all {
-                    entry
-                        .file_name()
-                        .to_str()
-                        .map(|s| s != "." && s != "..")
-                        .unwrap_or(true)
-                } else {
-                    !entry
-                        .file_name()
-                        .to_str()
-                        .map(|s| s.starts_with("."))
-                        .unwrap_or(false)
-                }
+        let contents = fs::read(manifest)?;
+        let separator = if contents.contains(&0) { 0 } else { b'\n' };
+
+        let paths: Vec<PathBuf> = contents
+            .split(|&b| b == separator)
+            .map(|raw| {
+                String::from_utf8_lossy(raw)
+                    .trim_end_matches('\r')
+                    .to_string()
             })
-            .take(limit.unwrap_or_else(|| std::usize::MAX))
+            .filter(|line| !line.is_empty())
+            .map(PathBuf::from)
             .collect();
+        read_named(&paths, args, true)
+    }
 
-        if let Some(max_depth) = max_depth {
-            let mut i = 0;
-            while i < entries.len() {
-                let entry = &entries[i];
-                if entry.path().components().count() > max_depth {
-                    entries.remove(i);
-                } else {
-                    i += 1;
+    /// Splits command-line operands the way GNU ls orders them: everything
+    /// that is not a directory is listed first, as one group shown by path,
+    /// then each directory is walked in the active sort order, so `-t` puts
+    /// the newest first and `-r` reverses it.
+    /// Missing operands are reported and make the exit status 1. With
+    /// `--max-depth=0` every operand is listed as itself.
+    pub fn operands(
+        paths: &[PathBuf],
+        args: &Arguments,
+    ) -> Result<(Vec<DirEntry>, Vec<PathBuf>), Box<dyn Error>> {
+        let mut files = Vec::new();
+        let mut dirs = Vec::new();
+        for path in paths {
+            let is_dir = fs::metadata(path).is_ok_and(|metadata| metadata.is_dir());
+            if is_dir && args.max_depth != Some(0) {
+                dirs.push(path.clone());
+                continue;
+            }
+            // A dangling symlink is still listed.
+            match fs::symlink_metadata(path) {
+                Ok(_) => files.push(path.clone()),
+                Err(err) => {
+                    let err = err.to_string();
+                    eprintln!(
+                        "lsr: cannot access '{}': {}",
+                        path.display(),
+                        error_text(&err)
+                    );
+                    UNREADABLE.store(true, Ordering::Relaxed);
                 }
             }
         }
+        let mut dirs: Vec<DirEntry> = dirs.iter().map(|dir| DirEntry::from_path(dir)).collect();
+        sort::sort_entries(
+            &mut dirs,
+            args.sort_field,
+            args.time.unwrap_or(TimeSort::Mtime),
+            args.reverse,
+        );
+        let dirs = dirs.iter().map(DirEntry::path).collect();
+        let files = if files.is_empty() {
+            Vec::new()
+        } else {
+            read_named(&files, args, false)?
+        };
+        Ok((files, dirs))
+    }
+
+    /// Entries for explicitly named paths, ordered for display. Each path is
+    /// looked up on its own, so a directory that can be searched but not
+    /// read still yields the names under it. Operands are shown whatever
+    /// the listing filters say, as in GNU ls; with `filter` (for
+    /// `--files-from`) they apply as if each path were met in its directory.
+    /// Paths are kept as given, so `.` is shown as `.`.
+    fn read_named(
+        paths: &[PathBuf],
+        args: &Arguments,
+        filter: bool,
+    ) -> Result<Vec<DirEntry>, Box<dyn Error>> {
+        let mut entries = Vec::new();
+        let mut by_parent: Vec<(PathBuf, Vec<DirEntry>)> = Vec::new();
+        for path in paths {
+            if let Err(err) = fs::symlink_metadata(path) {
+                let err = err.to_string();
+                eprintln!(
+                    "lsr: cannot access '{}': {}",
+                    path.display(),
+                    error_text(&err)
+                );
+                UNREADABLE.store(true, Ordering::Relaxed);
+                continue;
+            }
+            let entry = DirEntry::from_path(path);
+            if !filter {
+                entries.push(entry);
+                continue;
+            }
+            let parent = match path.parent() {
+                Some(parent) if parent.as_os_str().is_empty() => Path::new("."),
+                Some(parent) => parent,
+                None => path,
+            };
+            match by_parent.iter_mut().find(|(dir, _)| dir == parent) {
+                Some((_, group)) => group.push(entry),
+                None => by_parent.push((parent.to_path_buf(), vec![entry])),
+            }
+        }
 
+        // Named paths are shown even when hidden, as with `ls .profile`.
+        for (parent, group) in by_parent {
+            let mut listed = filtered(&parent, group, args, true, 0);
+            listed.retain(|entry| link_filter(args).matches(entry));
+            entries.extend(listed);
+        }
+        // Names are shown with their directory, so sort by the whole path.
+        entries.sort_by_key(|entry| entry.path());
+        if args.sort_field == SortField::Name {
+            if args.reverse {
+                entries.reverse();
+            }
+            if let Some(limit) = args.limit {
+                entries.truncate(limit);
+            }
+        } else {
+            order(&mut entries, args);
+        }
         Ok(entries)
     }
-}
 
-mod list {
-    use chrono::offset::Utc;
-    use chrono::DateTime;
-    use std::error::Error;
-    use std::fs::DirEntry;
+    /// Calls `visit` with `root`'s entries and, with `-R`, then descends
+    /// depth-first into each listed subdirectory in display order, so group
+    /// order follows the active sort key and `-r` (with `-t`, the newest
+    /// directory's group comes first). Symlinks to directories are only
+    /// followed with `-L`. `--parallel` reads directories
+    /// concurrently but visits them in exactly the same order.
+    ///
+    /// The serial walk hands each group to `visit` before descending, so
+    /// only the pending subdirectory paths of each level stay in memory.
+    /// The parallel walk reads the whole tree ahead; `--low-memory` turns
+    /// it off.
+    ///
+    /// Once `--max-total-entries` is reached the walk stops cleanly: the
+    /// group that crosses the cap is cut short and nothing after it is
+    /// visited.
+    pub fn walk<F>(root: &Path, args: &Arguments, visit: &mut F) -> Result<(), Box<dyn Error>>
+    where
+        F: FnMut(&Path, Vec<DirEntry>) -> Result<(), Box<dyn Error>>,
+    {
+        let visit = &mut |dir: &Path, mut entries: Vec<DirEntry>| {
+            if admit(&mut entries, args) {
+                return visit(dir, entries);
+            }
+            if !entries.is_empty() {
+                visit(dir, entries)?;
+            }
+            Err(Truncated.into())
+        };
+        let walked = if args.max_depth == Some(0) {
+            // Depth 0 is the root itself, as with `ls -d`.
+            let entries = read_named(&[root.to_path_buf()], args, false)?;
+            visit(root, entries)
+        } else if args.prune_empty && args.recursive {
+            walk_pruned(root, args, visit)
+        } else if args.parallel && args.recursive && !args.low_memory {
+            walk_parallel(root, args, visit)
+        } else {
+            walk_serial(root, args, 0, visit)
+        };
+        match walked {
+            Err(err) if err.is::<Truncated>() => Ok(()),
+            walked => walked,
+        }
+    }
 
-    use crate::args::TimeSort;
+    /// Unwinds a walk once `--max-total-entries` is reached; `walk` turns
+    /// it back into success.
+    #[derive(Debug)]
+    struct Truncated;
 
-    pub fn list_dir(
+    impl fmt::Display for Truncated {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            write!(f, "output truncated")
+        }
+    }
+
+    impl Error for Truncated {}
+
+    /// Entries let through so far under `--max-total-entries`.
+    static ADMITTED: AtomicUsize = AtomicUsize::new(0);
+
+    /// Set once `--max-total-entries` cut the output short.
+    static TRUNCATED: AtomicBool = AtomicBool::new(false);
+
+    /// Applies `--max-total-entries` across the whole run: keeps as many of
+    /// `entries` as still fit. Returns false, reporting the truncation once
+    /// on stderr, if any had to be dropped or the cap had already been
+    /// reached, in which case nothing more should be listed.
+    pub fn admit(entries: &mut Vec<DirEntry>, args: &Arguments) -> bool {
+        let Some(cap) = args.max_total_entries else {
+            return true;
+        };
+        let admitted = ADMITTED.load(Ordering::Relaxed);
+        let room = cap.saturating_sub(admitted);
+        let complete = admitted < cap && entries.len() <= room;
+        entries.truncate(room);
+        ADMITTED.fetch_add(entries.len(), Ordering::Relaxed);
+        if !complete && !TRUNCATED.swap(true, Ordering::Relaxed) {
+            eprintln!("... (output truncated)");
+        }
+        complete
+    }
+
+    /// Whether `--max-total-entries` stopped the listing early.
+    pub fn truncated() -> bool {
+        TRUNCATED.load(Ordering::Relaxed)
+    }
+
+    /// `walk` over each of `roots` in turn.
+    pub fn walk_each<F>(
+        roots: &[PathBuf],
+        args: &Arguments,
+        visit: &mut F,
+    ) -> Result<(), Box<dyn Error>>
+    where
+        F: FnMut(&Path, Vec<DirEntry>) -> Result<(), Box<dyn Error>>,
+    {
+        for root in roots {
+            if truncated() {
+                break;
+            }
+            walk(root, args, visit)?;
+        }
+        Ok(())
+    }
+
+    /// Whether entries `depth` levels below the root fall inside the
+    /// `--min-depth`/`--max-depth` band. The root's own entries are depth 1.
+    fn in_band(args: &Arguments, depth: usize) -> bool {
+        depth >= args.min_depth.unwrap_or(0) && depth <= args.max_depth.unwrap_or(usize::MAX)
+    }
+
+    /// Subdirectories of a directory at `depth` worth descending into: only
+    /// with `-R`, and only while their entries could still be in band.
+    fn subdirectories(
         entries: &[DirEntry],
-        escape: bool,
-        time: Option<TimeSort>,
-        classify: bool,
-    ) -> Result<(), Box<dyn Error>> {
-        for entry in entries {
-            let path = entry.path();
-            let mut components = path.components();
-            let file_name = components
-                .next_back()
-                .unwrap()
-                .as_os_str()
-                .to_string_lossy();
-
-            if escape {
-                print!("{}", escape_string(&file_name));
-            } else {
-                print!("{}", file_name);
+        args: &Arguments,
+        depth: usize,
+    ) -> io::Result<Vec<PathBuf>> {
+        let mut subdirs = Vec::new();
+        if args.recursive && depth + 2 <= args.max_depth.unwrap_or(usize::MAX) {
+            for entry in entries {
+                let file_type = entry.file_type()?;
+                // `-L` follows symlinked directories too; `already_listed`
+                // stops the walk going round a cycle.
+                if file_type.is_dir()
+                    || (args.dereference && file_type.is_symlink() && entry.path().is_dir())
+                {
+                    subdirs.push(entry.path());
+                }
             }
+        }
+        Ok(subdirs)
+    }
 
-            if classify {
-                let file_type = match entry.file_type()? {
-                    t if t.is_dir() => '/',
-                    t if t.is_symlink() => '@',
-                    t if t.is_file() => ' ',
-                    _ => ' ',
-                };
-                print!("{}", file_type);
+    /// `depth` is how far `root` is below the listing root.
+    fn walk_serial<F>(
+        root: &Path,
+        args: &Arguments,
+        depth: usize,
+        visit: &mut F,
+    ) -> Result<(), Box<dyn Error>>
+    where
+        F: FnMut(&Path, Vec<DirEntry>) -> Result<(), Box<dyn Error>>,
+    {
+        if already_listed(root, depth) {
+            report_loop(root);
+            return Ok(());
+        }
+        let (entries, subdirs) = match read_dir(root, args, depth) {
+            Ok(read) => read,
+            Err(err) if depth > 0 => {
+                report_unreadable(root, &err.to_string());
+                return Ok(());
             }
+            Err(err) => return Err(err),
+        };
 
-            if let Some(time) = time {
-                let metadata = entry.metadata()?;
+        if in_band(args, depth + 1) {
+            visit(root, entries)?;
+        }
+        for subdir in subdirs {
+            walk_serial(&subdir, args, depth + 1, visit)?;
+        }
+        Ok(())
+    }
 
-                let access_time: DateTime<Utc> = metadata.accessed()?.into();
-                let modified_time: DateTime<Utc> = metadata.modified()?.into();
-                let created_time: DateTime<Utc> = metadata.created()?.into();
+    /// `--prune-empty`: walks the whole tree first, then drops every
+    /// directory below the root with nothing left beneath it after
+    /// filtering, both its group and its entry in the parent's listing.
+    /// Directories `-R` did not descend into (past `--max-depth`) count as
+    /// empty.
+    fn walk_pruned<F>(root: &Path, args: &Arguments, visit: &mut F) -> Result<(), Box<dyn Error>>
+    where
+        F: FnMut(&Path, Vec<DirEntry>) -> Result<(), Box<dyn Error>>,
+    {
+        let mut groups: Vec<(PathBuf, Vec<DirEntry>)> = Vec::new();
+        walk_serial(root, args, 0, &mut |dir, entries| {
+            groups.push((dir.to_path_buf(), entries));
+            Ok(())
+        })?;
 
-                let time_string = match time {
-                    TimeSort::Atime => access_time.format("%b %e %R").to_string(),
-                    TimeSort::Mtime => modified_time.format("%b %e %R").to_string(),
-                    TimeSort::Ctime => created_time.format("%b %e %R").to_string(),
-                };
-                print!("  {}", time_string);
+        // Groups arrive in pre-order, so in reverse every directory's
+        // subgroups are judged before it.
+        let is_dir = |entry: &DirEntry| entry.file_type().is_ok_and(|t| t.is_dir());
+        let mut kept: HashSet<PathBuf> = HashSet::new();
+        for (dir, entries) in groups.iter().rev() {
+            if entries
+                .iter()
+                .any(|entry| !is_dir(entry) || kept.contains(&entry.path()))
+            {
+                kept.insert(dir.clone());
             }
+        }
 
-            println!();
+        for (dir, mut entries) in groups {
+            if dir != root && !kept.contains(&dir) {
+                continue;
+            }
+            entries.retain(|entry| !is_dir(entry) || kept.contains(&entry.path()));
+            visit(&dir, entries)?;
+        }
+        Ok(())
+    }
+
+    /// Set once an operand is missing, or any subdirectory could not be
+    /// read or was skipped as a symlink cycle.
+    static UNREADABLE: AtomicBool = AtomicBool::new(false);
+
+    /// Whether an operand was missing or the walk skipped a subdirectory it
+    /// could not read or had already listed; `main` exits with status 1 then, as GNU ls does for
+    /// such minor problems.
+    pub fn had_errors() -> bool {
+        UNREADABLE.load(Ordering::Relaxed)
+    }
+
+    /// Reports a subdirectory `-R` cannot read; the walk goes on with its
+    /// siblings. Only the root failing aborts the listing.
+    fn report_unreadable(path: &Path, error: &str) {
+        eprintln!(
+            "lsr: cannot open directory '{}': {}",
+            path.display(),
+            error_text(error)
+        );
+        UNREADABLE.store(true, Ordering::Relaxed);
+    }
+
+    /// An I/O error message without Rust's suffix:
+    /// "Permission denied (os error 13)" -> "Permission denied".
+    fn error_text(error: &str) -> &str {
+        match error.rfind(" (os error ") {
+            Some(index) if error.ends_with(')') => &error[..index],
+            _ => error,
+        }
+    }
+
+    /// Whether `path`, `depth` levels below the listing root, is the same
+    /// directory as one of the ancestors being listed above it. Only a
+    /// symlink followed with `-L` can lead back up the tree.
+    #[cfg(unix)]
+    fn already_listed(path: &Path, depth: usize) -> bool {
+        use std::os::unix::fs::MetadataExt;
+        let identity = |path: &Path| fs::metadata(path).map(|m| (m.dev(), m.ino())).ok();
+        if depth == 0 || !fs::symlink_metadata(path).is_ok_and(|m| m.file_type().is_symlink()) {
+            return false;
+        }
+        let Some(this) = identity(path) else {
+            return false;
+        };
+        path.ancestors()
+            .skip(1)
+            .take(depth)
+            .any(|ancestor| identity(ancestor) == Some(this))
+    }
+
+    #[cfg(not(unix))]
+    fn already_listed(_path: &Path, _depth: usize) -> bool {
+        false
+    }
+
+    /// Reports a directory cycle with GNU ls's wording; the walk goes on
+    /// with the siblings.
+    fn report_loop(path: &Path) {
+        eprintln!(
+            "lsr: '{}': not listing already-listed directory",
+            path.display()
+        );
+        UNREADABLE.store(true, Ordering::Relaxed);
+    }
+
+    /// A directory read ahead of time by the parallel walker.
+    struct Group {
+        path: PathBuf,
+        entries: Vec<DirEntry>,
+        /// False when the entries fall outside the depth band.
+        shown: bool,
+        /// Why a subdirectory could not be read.
+        error: Option<String>,
+        /// Skipped as a symlink back to a directory above it.
+        looped: bool,
+        children: Vec<Group>,
+    }
+
+    /// A subdirectory either handed to another thread or read inline.
+    enum Pending<'scope> {
+        Spawned(thread::ScopedJoinHandle<'scope, Result<Group, String>>),
+        Done(Result<Group, String>),
+    }
+
+    fn walk_parallel<F>(root: &Path, args: &Arguments, visit: &mut F) -> Result<(), Box<dyn Error>>
+    where
+        F: FnMut(&Path, Vec<DirEntry>) -> Result<(), Box<dyn Error>>,
+    {
+        let threads = thread::available_parallelism().map_or(1, |n| n.get());
+        // Threads beyond the calling one that may be busy at any time.
+        let spare = AtomicUsize::new(threads.saturating_sub(1));
+
+        let tree = read_tree(root.to_path_buf(), args, 0, &spare)?;
+        emit(tree, visit)
+    }
+
+    /// Reads `path` and its subtree, handing subdirectories to new threads
+    /// while spare capacity remains and reading them inline otherwise.
+    fn read_tree(
+        path: PathBuf,
+        args: &Arguments,
+        depth: usize,
+        spare: &AtomicUsize,
+    ) -> Result<Group, String> {
+        if already_listed(&path, depth) {
+            return Ok(Group {
+                path,
+                entries: Vec::new(),
+                shown: false,
+                error: None,
+                looped: true,
+                children: Vec::new(),
+            });
         }
+        let (entries, subdirs) = match read_dir(&path, args, depth) {
+            Ok(read) => read,
+            // Reported when emitted, so it lands in the same place as with
+            // the serial walk.
+            Err(err) if depth > 0 => {
+                return Ok(Group {
+                    path,
+                    entries: Vec::new(),
+                    shown: false,
+                    error: Some(err.to_string()),
+                    looped: false,
+                    children: Vec::new(),
+                })
+            }
+            Err(err) => return Err(err.to_string()),
+        };
+
+        let children = thread::scope(|scope| {
+            let pending: Vec<Pending> = subdirs
+                .into_iter()
+                .map(|subdir| {
+                    let acquired = spare
+                        .fetch_update(Ordering::AcqRel, Ordering::Acquire, |n| n.checked_sub(1))
+                        .is_ok();
+                    if acquired {
+                        Pending::Spawned(scope.spawn(move || {
+                            let group = read_tree(subdir, args, depth + 1, spare);
+                            spare.fetch_add(1, Ordering::AcqRel);
+                            group
+                        }))
+                    } else {
+                        Pending::Done(read_tree(subdir, args, depth + 1, spare))
+                    }
+                })
+                .collect();
 
+            pending
+                .into_iter()
+                .map(|pending| match pending {
+                    Pending::Spawned(handle) => handle
+                        .join()
+                        .unwrap_or_else(|_| Err("directory reader panicked".to_string())),
+                    Pending::Done(group) => group,
+                })
+                .collect::<Result<Vec<Group>, String>>()
+        })?;
+
+        Ok(Group {
+            path,
+            entries,
+            shown: in_band(args, depth + 1),
+            error: None,
+            looped: false,
+            children,
+        })
+    }
+
+    fn emit<F>(group: Group, visit: &mut F) -> Result<(), Box<dyn Error>>
+    where
+        F: FnMut(&Path, Vec<DirEntry>) -> Result<(), Box<dyn Error>>,
+    {
+        if let Some(error) = &group.error {
+            report_unreadable(&group.path, error);
+        }
+        if group.looped {
+            report_loop(&group.path);
+        }
+        if group.shown {
+            visit(&group.path, group.entries)?;
+        }
+        for child in group.children {
+            emit(child, visit)?;
+        }
         Ok(())
     }
+}
+
+mod json {
+    use chrono::{DateTime, Local, SecondsFormat, Utc};
+    use std::error::Error;
+    use std::fmt::Write;
+    use std::fs::FileType;
+    use std::time::SystemTime;
+
+    use crate::args::TimeUnit;
+    use crate::entries::DirEntry;
+    use crate::list;
+
+    /// Version of the entry object's shape, written to every object as
+    /// `schema_version`. Bump it, and update `SCHEMA`, whenever a field is
+    /// added, removed or changes meaning.
+    pub const SCHEMA_VERSION: u32 = 1;
+
+    /// JSON Schema (draft 2020-12) for one entry object, printed by
+    /// `--json-schema`. Hand-maintained alongside `Writer::write`.
+    pub const SCHEMA: &str = r#"{
+  "$schema": "https://json-schema.org/draft/2020-12/schema",
+  "$id": "https://github.com/sabry-awad97/ls-rust/schema/entry-v1.json",
+  "title": "lsr --json entry",
+  "type": "object",
+  "properties": {
+    "name": { "type": "string", "description": "File name, lossily decoded as UTF-8" },
+    "path": { "type": "string", "description": "Path as listed, lossily decoded as UTF-8" },
+    "type": {
+      "enum": ["file", "directory", "symlink", "fifo", "socket", "block_device", "char_device", "other"]
+    },
+    "size": { "type": "integer", "minimum": 0, "description": "Size in bytes" },
+    "modified": { "type": ["string", "null"], "format": "date-time" },
+    "accessed": { "type": ["string", "null"], "format": "date-time" },
+    "modified_relative": { "type": ["string", "null"], "description": "Only with --json-relative" },
+    "accessed_relative": { "type": ["string", "null"], "description": "Only with --json-relative" },
+    "schema_version": { "const": 1 }
+  },
+  "required": ["name", "path", "type", "size", "modified", "accessed", "schema_version"],
+  "additionalProperties": false
+}"#;
+
+    /// Streams entries as one JSON array, one object per line:
+    /// `{"name":…,"path":…,"type":…,"size":…,"modified":…,"accessed":…,
+    /// "schema_version":…}`. Times are RFC 3339 in UTC, or `null` when
+    /// unavailable.
+    pub struct Writer {
+        /// "Now" for `--json-relative`'s `*_relative` fields, if requested.
+        relative_to: Option<DateTime<Local>>,
+        granularity: Option<TimeUnit>,
+        first: bool,
+    }
 
-    fn escape_string(s: &str) -> String {
-        let mut escaped = String::new();
+    impl Writer {
+        pub fn new(relative_to: Option<DateTime<Local>>, granularity: Option<TimeUnit>) -> Writer {
+            Writer {
+                relative_to,
+                granularity,
+                first: true,
+            }
+        }
+
+        pub fn write(&mut self, entries: &[DirEntry]) -> Result<(), Box<dyn Error>> {
+            for entry in entries {
+                let metadata = entry.metadata()?;
+                let mut object = String::from("{");
+                write!(
+                    object,
+                    "\"name\":{},\"path\":{},\"type\":\"{}\",\"size\":{}",
+                    string(&entry.file_name().to_string_lossy()),
+                    string(&entry.path().to_string_lossy()),
+                    type_name(&metadata.file_type()),
+                    metadata.len()
+                )?;
+                self.time(&mut object, "modified", metadata.modified().ok())?;
+                self.time(&mut object, "accessed", metadata.accessed().ok())?;
+                write!(object, ",\"schema_version\":{}}}", SCHEMA_VERSION)?;
+
+                print!("{}\n{}", if self.first { "[" } else { "," }, object);
+                self.first = false;
+            }
+            Ok(())
+        }
+
+        pub fn finish(self) {
+            println!("{}]", if self.first { "[" } else { "\n" });
+        }
+
+        fn time(
+            &self,
+            object: &mut String,
+            field: &str,
+            time: Option<SystemTime>,
+        ) -> Result<(), Box<dyn Error>> {
+            let time = time.map(DateTime::<Utc>::from);
+            match time {
+                Some(time) => write!(
+                    object,
+                    ",\"{}\":\"{}\"",
+                    field,
+                    time.to_rfc3339_opts(SecondsFormat::Secs, true)
+                )?,
+                None => write!(object, ",\"{}\":null", field)?,
+            }
+            if let Some(now) = self.relative_to {
+                match time {
+                    Some(time) => {
+                        let relative = list::humanize_duration(
+                            now.timestamp() - time.timestamp(),
+                            self.granularity,
+                        );
+                        write!(object, ",\"{}_relative\":{}", field, string(&relative))?
+                    }
+                    None => write!(object, ",\"{}_relative\":null", field)?,
+                }
+            }
+            Ok(())
+        }
+    }
+
+    fn type_name(file_type: &FileType) -> &'static str {
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::FileTypeExt;
+            if file_type.is_fifo() {
+                return "fifo";
+            }
+            if file_type.is_socket() {
+                return "socket";
+            }
+            if file_type.is_block_device() {
+                return "block_device";
+            }
+            if file_type.is_char_device() {
+                return "char_device";
+            }
+        }
+        match file_type {
+            t if t.is_dir() => "directory",
+            t if t.is_symlink() => "symlink",
+            t if t.is_file() => "file",
+            _ => "other",
+        }
+    }
+
+    /// A JSON string literal.
+    fn string(s: &str) -> String {
+        let mut out = String::with_capacity(s.len() + 2);
+        out.push('"');
         for c in s.chars() {
-            if c.is_ascii_graphic() {
-                escaped.push(c);
-            } else {
-                escaped.push_str(&format!("\\{:03o}", c as u8));
+            match c {
+                '"' => out.push_str("\\\""),
+                '\\' => out.push_str("\\\\"),
+                '\n' => out.push_str("\\n"),
+                '\t' => out.push_str("\\t"),
+                c if (c as u32) < 0x20 => write!(out, "\\u{:04x}", c as u32).unwrap(),
+                c => out.push(c),
             }
         }
-        escaped
+        out.push('"');
+        out
+    }
+}
+
+mod terminal {
+    use std::env;
+    use std::io::{self, IsTerminal};
+
+    /// Whether stdout should be treated as a terminal. `LSR_FORCE_TTY=1` or
+    /// `LSR_FORCE_TTY=0` overrides detection; this is an internal hook so
+    /// tests can exercise TTY-only defaults without a real terminal.
+    pub fn is_tty() -> bool {
+        match env::var("LSR_FORCE_TTY").as_deref() {
+            Ok("1") => true,
+            Ok("0") => false,
+            _ => io::stdout().is_terminal(),
+        }
+    }
+
+    /// Output width: `--width` if given, then `$COLUMNS`, then the size of
+    /// the terminal, falling back to 80 columns.
+    pub fn width(requested: Option<usize>) -> usize {
+        if let Some(width) = requested {
+            return width;
+        }
+        if let Some(columns) = env::var("COLUMNS")
+            .ok()
+            .and_then(|columns| columns.parse().ok())
+            .filter(|&columns: &usize| columns > 0)
+        {
+            return columns;
+        }
+        window_width().unwrap_or(80)
+    }
+
+    #[cfg(unix)]
+    fn window_width() -> Option<usize> {
+        let mut size: libc::winsize = unsafe { std::mem::zeroed() };
+        let result = unsafe { libc::ioctl(libc::STDOUT_FILENO, libc::TIOCGWINSZ, &mut size) };
+        if result == 0 && size.ws_col > 0 {
+            Some(size.ws_col as usize)
+        } else {
+            None
+        }
+    }
+
+    #[cfg(not(unix))]
+    fn window_width() -> Option<usize> {
+        None
     }
 }
 
 fn main() -> Result<(), Box<dyn Error>> {
     let args = args::parse_args()?;
-    let path = args.path.unwrap_or_else(|| ".".to_string());
-    let path = Path::new(&path);
+    let paths: Vec<PathBuf> = if args.paths.is_empty() {
+        vec![PathBuf::from(".")]
+    } else {
+        args.paths.iter().map(PathBuf::from).collect()
+    };
+    if args.debug_args {
+        eprintln!("lsr: resolved arguments: {:#?}", args);
+        eprintln!(
+            "lsr: stdout is a terminal: {}; width: {}",
+            terminal::is_tty(),
+            terminal::width(args.width)
+        );
+    }
+
+    let time = match args.time {
+        None if args.time_relative || args.time_style.is_some() => Some(args::TimeSort::Mtime),
+        time => time,
+    };
+    let relative_to = if args.time_relative {
+        Some(args.at.unwrap_or_else(Local::now))
+    } else {
+        None
+    };
+
+    if args.stat {
+        if paths.len() > 1 {
+            return Err("--stat takes a single path".into());
+        }
+        return stat::print_stat(&paths[0]);
+    }
+
+    if args.json_schema {
+        println!("{}", json::SCHEMA);
+        return Ok(());
+    }
+
+    if args.unescape {
+        let eol = if args.zero { b'\0' } else { b'\n' };
+        return list::unescape_stream(std::io::stdin().lock(), std::io::stdout().lock(), eol);
+    }
+
+    if args.rollup {
+        let mut skipped = false;
+        for path in &paths {
+            skipped |= summary::print_rollup(
+                path,
+                args.max_depth,
+                args.human_readable,
+                args.rollup_follow_symlinks,
+            )?;
+        }
+        if skipped {
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+
+    if args.git_modified {
+        git::load(&paths)?;
+    }
+
+    // Manifest entries stand in for the operands, as one group of files.
+    let (mut files, dirs) = match &args.files_from {
+        Some(manifest) => (walk::read_manifest(Path::new(manifest), &args)?, Vec::new()),
+        None => walk::operands(&paths, &args)?,
+    };
+    walk::admit(&mut files, &args);
+
+    if let Some(command) = &args.exec {
+        let mut entries = files;
+        walk::walk_each(&dirs, &args, &mut |_, group| {
+            entries.extend(group);
+            Ok(())
+        })?;
+        exec::exec_entries(command, &entries, args.confirm)?;
+        exit_if_unreadable();
+        exit_if_truncated();
+        exit_if_empty(&args, entries.len());
+        return Ok(());
+    }
+
+    if args.audit_perms {
+        let mut audit = audit::Audit::default();
+        audit.add(&files)?;
+        let mut shown = files.len();
+        walk::walk_each(&dirs, &args, &mut |_, group| {
+            shown += group.len();
+            audit.add(&group)
+        })?;
+        if audit.print() {
+            std::process::exit(1);
+        }
+        exit_if_unreadable();
+        exit_if_truncated();
+        exit_if_empty(&args, shown);
+        return Ok(());
+    }
+
+    if args.json {
+        let relative_to = args
+            .json_relative
+            .then(|| args.at.unwrap_or_else(Local::now));
+        let mut out = json::Writer::new(relative_to, args.relative_granularity);
+        out.write(&files)?;
+        let mut shown = files.len();
+        walk::walk_each(&dirs, &args, &mut |_, group| {
+            shown += group.len();
+            out.write(&group)
+        })?;
+        out.finish();
+        exit_if_unreadable();
+        exit_if_truncated();
+        exit_if_empty(&args, shown);
+        return Ok(());
+    }
+
+    let mut theme = color::Theme::builtin(args.theme);
+    // LS_COLORS customizes the default palette only; --theme-file wins over both.
+    if args.theme == args::ThemeName::Default {
+        if let Ok(spec) = std::env::var("LS_COLORS") {
+            theme.apply_ls_colors(&spec);
+        }
+    }
+    if let Some(file) = &args.theme_file {
+        let spec = fs::read_to_string(file).map_err(|err| format!("{}: {}", file, err))?;
+        theme
+            .apply(&spec)
+            .map_err(|err| format!("{}: {}", file, err))?;
+    }
+
+    let options = list::ListOptions {
+        // NUL-terminated output is for machines, so names are never altered.
+        control_chars: if args.zero && !args.escape {
+            args::ControlChars::Show
+        } else {
+            args::resolve_control_chars(&args, terminal::is_tty())
+        },
+        time,
+        indicator_style: if args.no_self_indicator && args.max_depth == Some(0) {
+            args::IndicatorStyle::None
+        } else {
+            args.indicator_style.unwrap_or(args::IndicatorStyle::None)
+        },
+        relative_to,
+        relative_granularity: args.relative_granularity,
+        time_style: args
+            .time_style
+            .clone()
+            .map(|style| (style, args.at.unwrap_or_else(Local::now))),
+        hide_extension: args.hide_extension,
+        symlink_chain: args.symlink_chain,
+        blocks: args.size,
+        number: args.number.is_some(),
+        mark_hardlinks: args.mark_hardlinks,
+        align_ext: args.align_ext,
+        total_position: args.total_position,
+        device_id: args.device_id,
+        mark_mounts: args.mark_mounts,
+        sanitize_names: !args.raw_names && (args.sanitize_names || terminal::is_tty()),
+        dereference: args.dereference,
+        escape_style: args.escape_style.unwrap_or(args::EscapeStyle::Octal),
+        verify_quoting: args.verify_quoting,
+        // Records are one per "line" with --zero.
+        format: if args.zero {
+            args::Format::SingleColumn
+        } else {
+            args.format.unwrap_or(args::Format::SingleColumn)
+        },
+        width: args.width,
+        wrap_names: args.wrap_names,
+        color: match args.color {
+            Some(None) | Some(Some(args::ColorWhen::Always)) => Some(args.color_by),
+            Some(Some(args::ColorWhen::Auto)) if terminal::is_tty() => Some(args.color_by),
+            _ => None,
+        },
+        theme,
+        smart_exec: args.smart_exec_color,
+        now: args.at.unwrap_or_else(Local::now),
+        file_size: args.file_size,
+        device_width: args.device_width,
+        blocks_width: args.blocks_width,
+        size_width: args.size_width,
+        truncate_columns: args.truncate_columns,
+        percent: args.percent,
+        human: args.human_readable,
+        zero: args.zero,
+        full_paths: args.files_from.is_some() || args.max_depth == Some(0),
+        paths_only: args.paths_only,
+    };
+    if args.debug_args {
+        eprintln!("lsr: listing options: {:#?}", options);
+    }
+
+    if args.newest || args.oldest {
+        // Only the best entry so far is kept while walking.
+        let mut best = sort::extreme_by_mtime(files, args.newest);
+        walk::walk_each(&dirs, &args, &mut |_, group| {
+            best = sort::extreme_by_mtime(best.take().into_iter().chain(group), args.newest);
+            Ok(())
+        })?;
+        let found = best.is_some();
+        if let Some(entry) = best {
+            list::list_dir(&[entry], &options, &mut 0)?;
+        }
+        exit_if_unreadable();
+        exit_if_truncated();
+        exit_if_empty(&args, found as usize);
+        return Ok(());
+    }
 
-    let entries = entries::read_entries(path, args.show_almost_all, args.max_depth, args.limit)?;
-    list::list_dir(&entries, args.escape, args.time, args.classify)?;
+    if let Some(out) = &args.split_output {
+        // Each directory operand would be mirrored onto the same files.
+        if dirs.len() > 1 {
+            return Err("--split-output takes a single directory to list".into());
+        }
+        // Listing files written inside the tree would show up in it.
+        fs::create_dir_all(out)
+            .map_err(|err| format!("cannot create directory '{}': {}", out, err))?;
+        for dir in &dirs {
+            if fs::canonicalize(out)?.starts_with(fs::canonicalize(dir)?) {
+                return Err(format!(
+                    "--split-output directory '{}' is inside the listed tree",
+                    out
+                )
+                .into());
+            }
+        }
+    }
+
+    let mut totals = summary::SizeByType::new(args.count_links);
+    let mut first = true;
+    let mut listed = 0;
+    let mut shown = 0;
+    // As in GNU ls, directories are named once there is more than one
+    // operand, even without -R.
+    let headers =
+        (args.recursive || paths.len() > 1) && args.split_output.is_none() && !args.paths_only;
+
+    // File operands come first, as a single group without a header.
+    if !files.is_empty() {
+        let file_options = list::ListOptions {
+            full_paths: true,
+            ..options.clone()
+        };
+        list::list_dir(&files, &file_options, &mut listed)?;
+        shown += files.len();
+        if args.size_by_type {
+            totals.add(&files)?;
+        }
+        first = false;
+    }
+
+    for root in &dirs {
+        if walk::truncated() {
+            break;
+        }
+        walk::walk(root, &args, &mut |dir, entries| {
+            if args.number == Some(Some(args::Numbering::PerDir)) {
+                listed = 0;
+            }
+            // Held across the header and the entries (the lock is reentrant)
+            // so that a directory's group reaches stdout in one piece.
+            let _group = std::io::stdout().lock();
+            if headers {
+                // GNU separates directory groups with a blank line.
+                if !first {
+                    print!("{}", options.eol());
+                }
+                print!("{}:{}", dir.display(), options.eol());
+            }
+            first = false;
+
+            match &args.split_output {
+                Some(out) => {
+                    let file = walk::split_output_path(Path::new(out), root, dir, &entries)?;
+                    let listing = list::format_dir(&entries, &options, &mut listed)?;
+                    fs::write(&file, listing)
+                        .map_err(|err| format!("cannot write '{}': {}", file.display(), err))?;
+                }
+                None => list::list_dir(&entries, &options, &mut listed)?,
+            }
+            shown += entries.len();
+            if args.size_by_type {
+                totals.add(&entries)?;
+            }
+            Ok(())
+        })?;
+    }
+
+    if args.size_by_type {
+        totals.print(args.human_readable);
+    }
 
+    exit_if_unreadable();
+
+    exit_if_truncated();
+    exit_if_empty(&args, shown);
     Ok(())
 }
+
+/// Exit status for `--fail-on-empty`, distinct from the 1 of any error.
+const EXIT_EMPTY: i32 = 3;
+
+/// Exits with `EXIT_EMPTY` when `--fail-on-empty` is given and nothing was
+/// listed after filtering.
+fn exit_if_empty(args: &args::Arguments, shown: usize) {
+    if args.fail_on_empty && shown == 0 {
+        std::process::exit(EXIT_EMPTY);
+    }
+}
+
+/// Exit status when `--max-total-entries` cut the listing short.
+const EXIT_TRUNCATED: i32 = 4;
+
+/// Exits with `EXIT_TRUNCATED` once `--max-total-entries` dropped entries.
+fn exit_if_truncated() {
+    if walk::truncated() {
+        std::process::exit(EXIT_TRUNCATED);
+    }
+}
+
+/// Exits with status 1 when `-R` skipped an unreadable subdirectory.
+fn exit_if_unreadable() {
+    if walk::had_errors() {
+        std::process::exit(1);
+    }
+}
@@ -1,8 +1,11 @@
 use assert_cmd::prelude::*;
 use predicates::prelude::*;
+use std::fs;
+use std::path::PathBuf;
 use std::process::Command;
+use std::time::{Duration, SystemTime};
 
-const PROG_NAME: &'static str = "lsr";
+const PROG_NAME: &str = "lsr";
 
 #[test]
 fn test_ls() {
@@ -42,3 +45,2927 @@ fn test_almost_all() {
         .success()
         .stdout(predicate::str::contains(".gitignore"));
 }
+
+/// Creates an empty scratch directory for a test under cargo's tmp dir.
+fn fixture(name: &str) -> PathBuf {
+    let dir = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join(name);
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+#[test]
+fn test_size_by_type() {
+    let dir = fixture("size_by_type");
+    fs::write(dir.join("a.txt"), "hello").unwrap();
+    fs::write(dir.join("b.txt"), "world!").unwrap();
+    fs::create_dir(dir.join("sub")).unwrap();
+
+    let mut cmd = Command::cargo_bin(PROG_NAME).unwrap();
+
+    // Set the command-line arguments and options
+    cmd.arg("--size-by-type").arg(&dir);
+
+    // Run the command and check the output
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::is_match(r"(?m)^files\s+2\s+11$").unwrap())
+        .stdout(predicate::str::is_match(r"(?m)^directories\s+1\s+\d+$").unwrap())
+        .stdout(predicate::str::contains("symlinks").not());
+}
+
+/// Sets a file's or directory's modification time to `secs` seconds after
+/// the Unix epoch.
+fn set_mtime(path: &std::path::Path, secs: u64) {
+    fs::File::open(path)
+        .unwrap()
+        .set_modified(SystemTime::UNIX_EPOCH + Duration::from_secs(secs))
+        .unwrap();
+}
+
+#[test]
+fn test_time_relative_at() {
+    let dir = fixture("time_relative_at");
+    let file = dir.join("old.log");
+    fs::write(&file, "").unwrap();
+    // 2020-01-01T00:00:00Z
+    set_mtime(&file, 1_577_836_800);
+
+    let mut cmd = Command::cargo_bin(PROG_NAME).unwrap();
+
+    // Set the command-line arguments and options
+    cmd.arg("--time-relative")
+        .arg("--at=2020-01-04T00:00:00Z")
+        .arg(&dir);
+
+    // Run the command and check the output
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("old.log  3 days ago"));
+}
+
+#[test]
+fn test_time_style_recent_and_old() {
+    let dir = fixture("time_style");
+    // 2019-01-01T12:00:00Z, more than six months before --at.
+    fs::write(dir.join("a_old"), "").unwrap();
+    set_mtime(&dir.join("a_old"), 1_546_344_000);
+    // 2020-03-01T09:30:00Z, within six months of --at.
+    fs::write(dir.join("b_recent"), "").unwrap();
+    set_mtime(&dir.join("b_recent"), 1_583_055_000);
+
+    let at = "--at=2020-04-01T00:00:00Z";
+    assert_eq!(
+        listing(&dir, &[at, "--time-style=+%b %e  %Y\n%b %e %H:%M"]),
+        ["a_old  Jan  1  2019", "b_recent  Mar  1 09:30"]
+    );
+    // A single format applies to every time.
+    assert_eq!(
+        listing(&dir, &[at, "--time-style=+%Y-%m-%d"]),
+        ["a_old  2019-01-01", "b_recent  2020-03-01"]
+    );
+    assert_eq!(
+        listing(&dir, &[at, "--time-style=iso"]),
+        ["a_old  2019-01-01 ", "b_recent  03-01 09:30"]
+    );
+
+    let mut cmd = Command::cargo_bin(PROG_NAME).unwrap();
+
+    // Set the command-line arguments and options
+    cmd.arg("--time-style=+%Q").arg(&dir);
+
+    // Run the command and check the output
+    cmd.assert()
+        .failure()
+        .stderr(predicate::str::contains("invalid time format '%Q'"));
+}
+
+#[test]
+fn test_time_style_uses_local_time() {
+    let dir = fixture("time_style_local");
+    // 2020-03-01T09:30:00Z, 18:30 in Tokyo.
+    fs::write(dir.join("file"), "").unwrap();
+    set_mtime(&dir.join("file"), 1_583_055_000);
+
+    let mut cmd = Command::cargo_bin(PROG_NAME).unwrap();
+
+    // Set the command-line arguments and options
+    cmd.env("TZ", "Asia/Tokyo")
+        .args(["--at=2020-04-01T00:00:00Z", "--time-style=long-iso"])
+        .arg(&dir);
+
+    // Run the command and check the output
+    cmd.assert().success().stdout("file  2020-03-01 18:30\n");
+
+    let mut full = Command::cargo_bin(PROG_NAME).unwrap();
+    full.env("TZ", "Asia/Tokyo")
+        .args(["--at=2020-04-01T00:00:00Z", "--time-style=full-iso"])
+        .arg(&dir);
+    full.assert()
+        .success()
+        .stdout("file  2020-03-01 18:30:00.000000000 +0900\n");
+}
+
+#[test]
+fn test_relative_granularity() {
+    let dir = fixture("relative_granularity");
+    let file = dir.join("old.log");
+    fs::write(&file, "").unwrap();
+    // 2020-01-01T00:00:00Z
+    set_mtime(&file, 1_577_836_800);
+
+    let cases: &[(&str, Option<&str>, &str)] = &[
+        ("2020-01-04T04:30:00Z", None, "3 days ago"),
+        ("2020-01-04T04:30:00Z", Some("hour"), "3 days 4 hours ago"),
+        (
+            "2020-01-04T04:30:00Z",
+            Some("second"),
+            "3 days 4 hours 30 minutes ago",
+        ),
+        ("2020-01-04T04:30:00Z", Some("year"), "3 days ago"),
+        ("2020-01-15T00:00:00Z", Some("day"), "2 weeks ago"),
+        ("2019-12-31T21:59:30Z", Some("minute"), "in 2 hours"),
+    ];
+
+    for (at, granularity, expected) in cases {
+        let mut cmd = Command::cargo_bin(PROG_NAME).unwrap();
+
+        // Set the command-line arguments and options
+        cmd.arg("--time-relative").arg(format!("--at={}", at));
+        if let Some(unit) = granularity {
+            cmd.arg(format!("--relative-granularity={}", unit));
+        }
+        cmd.arg(&dir);
+
+        // Run the command and check the output
+        cmd.assert()
+            .success()
+            .stdout(format!("old.log  {}\n", expected));
+    }
+}
+
+#[test]
+fn test_invalid_at() {
+    let mut cmd = Command::cargo_bin(PROG_NAME).unwrap();
+
+    // Set the command-line arguments and options
+    cmd.arg("--time-relative").arg("--at=yesterday").arg(".");
+
+    // Run the command and check the output
+    cmd.assert()
+        .failure()
+        .stderr(predicate::str::contains("invalid date-time 'yesterday'"));
+}
+
+#[test]
+fn test_exec_per_entry() {
+    let dir = fixture("exec_per_entry");
+    fs::write(dir.join("a.txt"), "").unwrap();
+    fs::write(dir.join("b.txt"), "").unwrap();
+
+    let mut cmd = Command::cargo_bin(PROG_NAME).unwrap();
+
+    // Set the command-line arguments and options
+    cmd.arg("--exec=echo found {}").arg(&dir);
+
+    // Run the command and check the output
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::is_match(r"(?m)^found .*a\.txt$").unwrap())
+        .stdout(predicate::str::is_match(r"(?m)^found .*b\.txt$").unwrap());
+}
+
+#[test]
+fn test_exec_embedded_placeholder() {
+    let dir = fixture("exec_embedded");
+    fs::write(dir.join("a.txt"), "").unwrap();
+
+    let mut cmd = Command::cargo_bin(PROG_NAME).unwrap();
+
+    // Set the command-line arguments and options
+    cmd.current_dir(&dir).args(["--exec=echo x{}y", "."]);
+
+    // Run the command and check the output
+    cmd.assert().success().stdout("x./a.txty\n");
+}
+
+#[test]
+fn test_exec_batch() {
+    let dir = fixture("exec_batch");
+    fs::write(dir.join("a.txt"), "").unwrap();
+    fs::write(dir.join("b.txt"), "").unwrap();
+
+    let mut cmd = Command::cargo_bin(PROG_NAME).unwrap();
+
+    // Set the command-line arguments and options
+    cmd.arg("--exec=echo {} +").arg(&dir);
+
+    // Run the command and check the output
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::is_match(r"^\S*\.txt \S*\.txt\n$").unwrap());
+}
+
+#[test]
+fn test_exec_failure_exit_code() {
+    let dir = fixture("exec_failure");
+    fs::write(dir.join("file"), "").unwrap();
+    fs::create_dir(dir.join("sub")).unwrap();
+
+    let mut cmd = Command::cargo_bin(PROG_NAME).unwrap();
+
+    // Set the command-line arguments and options
+    cmd.arg("--exec=test -f {}").arg(&dir);
+
+    // Run the command and check the output
+    cmd.assert()
+        .failure()
+        .stderr(predicate::str::contains("1 invocation(s) failed"));
+}
+
+#[test]
+fn test_hide_extension() {
+    let dir = fixture("hide_extension");
+    fs::write(dir.join("report.pdf"), "").unwrap();
+    fs::write(dir.join("Makefile"), "").unwrap();
+    fs::create_dir(dir.join("photos.d")).unwrap();
+
+    let mut cmd = Command::cargo_bin(PROG_NAME).unwrap();
+
+    // Set the command-line arguments and options
+    cmd.arg("--hide-extension").arg(&dir);
+
+    // Run the command and check the output
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::is_match(r"(?m)^report$").unwrap())
+        .stdout(predicate::str::is_match(r"(?m)^Makefile$").unwrap())
+        .stdout(predicate::str::is_match(r"(?m)^photos\.d$").unwrap());
+}
+
+#[cfg(unix)]
+#[test]
+fn test_only_executable() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let dir = fixture("only_executable");
+    fs::write(dir.join("run.sh"), "#!/bin/sh\n").unwrap();
+    fs::write(dir.join("notes.txt"), "").unwrap();
+    fs::set_permissions(dir.join("run.sh"), fs::Permissions::from_mode(0o755)).unwrap();
+    fs::set_permissions(dir.join("notes.txt"), fs::Permissions::from_mode(0o644)).unwrap();
+
+    let mut cmd = Command::cargo_bin(PROG_NAME).unwrap();
+
+    // Set the command-line arguments and options
+    cmd.arg("--only-executable").arg(&dir);
+
+    // Run the command and check the output
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("run.sh"))
+        .stdout(predicate::str::contains("notes.txt").not());
+}
+
+#[cfg(unix)]
+#[test]
+fn test_only_readable_and_writable() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let dir = fixture("only_readable_writable");
+    fs::write(dir.join("rw.txt"), "").unwrap();
+    fs::set_permissions(dir.join("rw.txt"), fs::Permissions::from_mode(0o600)).unwrap();
+
+    let mut cmd = Command::cargo_bin(PROG_NAME).unwrap();
+
+    // Set the command-line arguments and options
+    cmd.arg("--only-readable").arg("--only-writable").arg(&dir);
+
+    // Run the command and check the output
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("rw.txt"));
+}
+
+#[cfg(unix)]
+#[test]
+fn test_symlink_chain() {
+    use std::os::unix::fs::symlink;
+
+    let dir = fixture("symlink_chain");
+    fs::write(dir.join("target.txt"), "").unwrap();
+    symlink("target.txt", dir.join("hop")).unwrap();
+    symlink("hop", dir.join("link")).unwrap();
+    symlink("missing", dir.join("dangling")).unwrap();
+    symlink("loop_b", dir.join("loop_a")).unwrap();
+    symlink("loop_a", dir.join("loop_b")).unwrap();
+
+    let mut cmd = Command::cargo_bin(PROG_NAME).unwrap();
+
+    // Set the command-line arguments and options
+    cmd.arg("--symlink-chain").arg(&dir);
+
+    // Run the command and check the output
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("link -> hop -> target.txt\n"))
+        .stdout(predicate::str::contains("dangling -> missing [broken]"))
+        .stdout(predicate::str::contains(
+            "loop_a -> loop_b -> loop_a [cycle]",
+        ));
+}
+
+#[cfg(unix)]
+#[test]
+fn test_forced_tty_hides_control_chars() {
+    let dir = fixture("forced_tty");
+    fs::write(dir.join("bell\u{7}name"), "").unwrap();
+
+    let mut cmd = Command::cargo_bin(PROG_NAME).unwrap();
+
+    // Set the command-line arguments and options
+    cmd.env("LSR_FORCE_TTY", "1").arg(&dir);
+
+    // Run the command and check the output
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("bell?name"));
+}
+
+#[cfg(unix)]
+#[test]
+fn test_forced_pipe_keeps_raw_names() {
+    let dir = fixture("forced_pipe");
+    fs::write(dir.join("bell\u{7}name"), "").unwrap();
+
+    let mut cmd = Command::cargo_bin(PROG_NAME).unwrap();
+
+    // Set the command-line arguments and options
+    cmd.env("LSR_FORCE_TTY", "0").arg(&dir);
+
+    // Run the command and check the output
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("bell\u{7}name"));
+}
+
+#[test]
+fn test_size_blocks_total() {
+    let dir = fixture("size_blocks_total");
+    fs::write(dir.join("empty"), "").unwrap();
+
+    let mut cmd = Command::cargo_bin(PROG_NAME).unwrap();
+
+    // Set the command-line arguments and options
+    cmd.arg("-s").arg(&dir);
+
+    // Run the command and check the output
+    cmd.assert().success().stdout("total 0\n0 empty\n");
+}
+
+#[test]
+fn test_total_position() {
+    let dir = fixture("total_position");
+    fs::write(dir.join("empty"), "").unwrap();
+
+    assert_eq!(
+        listing(&dir, &["-s", "--total-position=before"]),
+        ["total 0", "0 empty"]
+    );
+    assert_eq!(
+        listing(&dir, &["-s", "--total-position=after"]),
+        ["0 empty", "total 0"]
+    );
+}
+
+#[cfg(unix)]
+#[test]
+fn test_size_blocks_matches_coreutils() {
+    let dir = fixture("size_blocks_coreutils");
+    fs::write(dir.join("empty"), "").unwrap();
+    fs::write(dir.join("small"), "x".repeat(100)).unwrap();
+    fs::write(dir.join("large"), "x".repeat(100_000)).unwrap();
+    fs::write(dir.join("huge"), "x".repeat(3_000_000)).unwrap();
+
+    for flags in ["-s", "-sh"] {
+        let expected = match Command::new("ls")
+            .env("LC_ALL", "C")
+            .arg(flags)
+            .arg(&dir)
+            .output()
+        {
+            Ok(output) if output.status.success() => output.stdout,
+            _ => return,
+        };
+
+        let mut cmd = Command::cargo_bin(PROG_NAME).unwrap();
+
+        // Set the command-line arguments and options
+        cmd.arg(flags).arg(&dir);
+
+        // Run the command and check the output
+        cmd.assert().success().stdout(expected);
+    }
+}
+
+#[test]
+fn test_size_blocks_human() {
+    let dir = fixture("size_blocks_human");
+    fs::write(dir.join("empty"), "").unwrap();
+
+    assert_eq!(listing(&dir, &["-sh"]), ["total 0", "0 empty"]);
+}
+
+#[test]
+fn test_modified_within_and_before() {
+    let dir = fixture("modified_within");
+    fs::write(dir.join("fresh.log"), "").unwrap();
+    fs::write(dir.join("stale.log"), "").unwrap();
+    set_mtime(&dir.join("stale.log"), 1_577_836_800);
+
+    let mut within = Command::cargo_bin(PROG_NAME).unwrap();
+
+    // Set the command-line arguments and options
+    within.arg("--modified-within=2h").arg(&dir);
+
+    // Run the command and check the output
+    within
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("fresh.log"))
+        .stdout(predicate::str::contains("stale.log").not());
+
+    let mut before = Command::cargo_bin(PROG_NAME).unwrap();
+    before.arg("--modified-before=1w").arg(&dir);
+    before
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("stale.log"))
+        .stdout(predicate::str::contains("fresh.log").not());
+}
+
+#[test]
+fn test_invalid_duration() {
+    let mut cmd = Command::cargo_bin(PROG_NAME).unwrap();
+
+    // Set the command-line arguments and options
+    cmd.arg("--modified-within=3x").arg(".");
+
+    // Run the command and check the output
+    cmd.assert()
+        .failure()
+        .stderr(predicate::str::contains("one of s, m, h, d, w"));
+}
+
+#[test]
+fn test_one_per_line_on_tty() {
+    let dir = fixture("one_per_line");
+    fs::write(dir.join("a"), "").unwrap();
+    fs::write(dir.join("b"), "").unwrap();
+    fs::write(dir.join("c"), "").unwrap();
+
+    for flag in ["-1", "--format=single-column"] {
+        let mut cmd = Command::cargo_bin(PROG_NAME).unwrap();
+
+        // Set the command-line arguments and options
+        cmd.env("LSR_FORCE_TTY", "1").arg(flag).arg(&dir);
+
+        // Run the command and check the output
+        cmd.assert()
+            .success()
+            .stdout(predicate::str::is_match(r"^(?:[abc]\n){3}$").unwrap());
+    }
+}
+
+#[test]
+fn test_color_by_type() {
+    let dir = fixture("color_by_type");
+    fs::create_dir(dir.join("sub")).unwrap();
+    fs::write(dir.join("plain.txt"), "").unwrap();
+
+    let mut cmd = Command::cargo_bin(PROG_NAME).unwrap();
+
+    // Set the command-line arguments and options
+    cmd.arg("--color=always").arg(&dir);
+
+    // Run the command and check the output
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("\x1b[01;34msub\x1b[0m"))
+        .stdout(predicate::str::is_match(r"(?m)^plain\.txt$").unwrap());
+}
+
+#[cfg(unix)]
+#[test]
+fn test_color_special_directories() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let dir = fixture("color_special_dirs");
+    for (name, mode) in [("other", 0o777), ("sticky", 0o1755), ("tmp", 0o1777)] {
+        fs::create_dir(dir.join(name)).unwrap();
+        fs::set_permissions(dir.join(name), fs::Permissions::from_mode(mode)).unwrap();
+    }
+
+    let mut cmd = Command::cargo_bin(PROG_NAME).unwrap();
+
+    // Set the command-line arguments and options
+    cmd.env_remove("LS_COLORS").arg("--color=always").arg(&dir);
+
+    // Run the command and check the output
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("\x1b[34;42mother\x1b[0m"))
+        .stdout(predicate::str::contains("\x1b[37;44msticky\x1b[0m"))
+        .stdout(predicate::str::contains("\x1b[30;42mtmp\x1b[0m"));
+
+    // LS_COLORS overrides the defaults; keys lsr has no use for are skipped.
+    let mut cmd = Command::cargo_bin(PROG_NAME).unwrap();
+    cmd.env("LS_COLORS", "rs=0:tw=01;31:fi=00:mi=bogus")
+        .arg("--color=always")
+        .arg(&dir);
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("\x1b[01;31mtmp\x1b[0m"));
+}
+
+#[test]
+fn test_color_theme() {
+    let dir = fixture("color_theme");
+    fs::create_dir(dir.join("sub")).unwrap();
+    fs::write(dir.join("main.rs"), "").unwrap();
+    let theme = fixture("color_theme_file").join("theme");
+    fs::write(&theme, "# mine\ndi=01;35\n*.rs=33:*.md=36\n").unwrap();
+
+    let mut cmd = Command::cargo_bin(PROG_NAME).unwrap();
+
+    // Set the command-line arguments and options
+    cmd.args(["--color=always", "--theme=light"]).arg(&dir);
+
+    // Run the command and check the output
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("\x1b[34msub\x1b[0m"))
+        .stdout(predicate::str::is_match(r"(?m)^main\.rs$").unwrap());
+
+    let mut custom = Command::cargo_bin(PROG_NAME).unwrap();
+    custom
+        .arg("--color=always")
+        .arg("--theme-file")
+        .arg(&theme)
+        .arg(&dir);
+    custom
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("\x1b[01;35msub\x1b[0m"))
+        .stdout(predicate::str::contains("\x1b[33mmain.rs\x1b[0m"));
+
+    fs::write(&theme, "di=blue\n").unwrap();
+    let mut invalid = Command::cargo_bin(PROG_NAME).unwrap();
+    invalid.arg("--theme-file").arg(&theme).arg(&dir);
+    invalid
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("invalid color 'blue' for 'di'"));
+}
+
+#[cfg(unix)]
+#[test]
+fn test_smart_exec_color() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let dir = fixture("smart_exec_color");
+    fs::write(dir.join("data.csv"), "a,b\n1,2\n").unwrap();
+    fs::write(dir.join("script.sh"), "#!/bin/sh\necho hi\n").unwrap();
+    fs::write(dir.join("binary"), b"\x7fELF\x02\x01").unwrap();
+    for name in ["data.csv", "script.sh", "binary"] {
+        fs::set_permissions(dir.join(name), fs::Permissions::from_mode(0o755)).unwrap();
+    }
+
+    let mut plain = Command::cargo_bin(PROG_NAME).unwrap();
+
+    // Set the command-line arguments and options
+    plain.arg("--color=always").arg(&dir);
+
+    // Run the command and check the output
+    plain
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("\x1b[01;32mdata.csv\x1b[0m"));
+
+    let mut smart = Command::cargo_bin(PROG_NAME).unwrap();
+    smart
+        .args(["--color=always", "--smart-exec-color"])
+        .arg(&dir);
+    smart
+        .assert()
+        .success()
+        .stdout(predicate::str::is_match(r"(?m)^data\.csv$").unwrap())
+        .stdout(predicate::str::contains("\x1b[01;32mscript.sh\x1b[0m"))
+        .stdout(predicate::str::contains("\x1b[01;32mbinary\x1b[0m"));
+}
+
+#[test]
+fn test_color_auto_when_piped() {
+    let dir = fixture("color_auto_piped");
+    fs::create_dir(dir.join("sub")).unwrap();
+
+    let mut cmd = Command::cargo_bin(PROG_NAME).unwrap();
+
+    // Set the command-line arguments and options
+    cmd.env("LSR_FORCE_TTY", "0").arg("--color=auto").arg(&dir);
+
+    // Run the command and check the output
+    cmd.assert().success().stdout("sub\n");
+}
+
+#[test]
+fn test_color_by_age() {
+    let dir = fixture("color_by_age");
+    fs::write(dir.join("fresh"), "").unwrap();
+    fs::write(dir.join("old"), "").unwrap();
+    set_mtime(&dir.join("old"), 1_577_836_800);
+
+    let mut cmd = Command::cargo_bin(PROG_NAME).unwrap();
+
+    // Set the command-line arguments and options
+    cmd.arg("--color=always").arg("--color-by=age").arg(&dir);
+
+    // Run the command and check the output
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("\x1b[01;32mfresh\x1b[0m"))
+        .stdout(predicate::str::contains("\x1b[90mold\x1b[0m"));
+
+    // Half an hour after `old` was written, it is the freshest bucket.
+    let mut at = Command::cargo_bin(PROG_NAME).unwrap();
+    at.args([
+        "--color=always",
+        "--color-by=age",
+        "--at=2020-01-01T00:30:00Z",
+    ])
+    .arg(&dir);
+    at.assert()
+        .success()
+        .stdout(predicate::str::contains("\x1b[01;32mold\x1b[0m"));
+}
+
+#[cfg(unix)]
+#[test]
+fn test_color_by_owner() {
+    use std::os::unix::fs::MetadataExt;
+
+    let dir = fixture("color_by_owner");
+    fs::write(dir.join("mine"), "").unwrap();
+    let palette = [
+        "31", "32", "33", "34", "35", "36", "91", "92", "93", "94", "95", "96",
+    ];
+    let uid = fs::metadata(dir.join("mine")).unwrap().uid() as usize;
+    let expected = format!("\x1b[{}mmine\x1b[0m", palette[uid % palette.len()]);
+
+    let mut cmd = Command::cargo_bin(PROG_NAME).unwrap();
+
+    // Set the command-line arguments and options
+    cmd.arg("--color").arg("--color-by=owner").arg(&dir);
+
+    // Run the command and check the output
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains(expected));
+}
+
+#[test]
+fn test_size_by_type_deduplicates_hard_links() {
+    let dir = fixture("size_by_type_hard_links");
+    fs::write(dir.join("a.txt"), "hello").unwrap();
+    fs::hard_link(dir.join("a.txt"), dir.join("b.txt")).unwrap();
+
+    let mut cmd = Command::cargo_bin(PROG_NAME).unwrap();
+
+    // Set the command-line arguments and options
+    cmd.arg("--size-by-type").arg(&dir);
+
+    // Run the command and check the output
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::is_match(r"(?m)^files\s+2\s+5$").unwrap());
+
+    let mut cmd = Command::cargo_bin(PROG_NAME).unwrap();
+    cmd.arg("--size-by-type").arg("--count-links").arg(&dir);
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::is_match(r"(?m)^files\s+2\s+10$").unwrap());
+}
+
+/// Lists `dir` with `args` and returns the names in output order.
+fn listing(dir: &std::path::Path, args: &[&str]) -> Vec<String> {
+    let output = Command::cargo_bin(PROG_NAME)
+        .unwrap()
+        .args(args)
+        .arg(dir)
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "lsr {:?} failed", args);
+    String::from_utf8(output.stdout)
+        .unwrap()
+        .lines()
+        .map(|line| line.to_string())
+        .collect()
+}
+
+#[test]
+fn test_sort_precedence() {
+    let dir = fixture("sort_precedence");
+    // a: largest, oldest; b: smallest, newest; c: in between.
+    fs::write(dir.join("a.zip"), "xxx").unwrap();
+    fs::write(dir.join("b.txt"), "x").unwrap();
+    fs::write(dir.join("c.md"), "xx").unwrap();
+    set_mtime(&dir.join("a.zip"), 1_000_000);
+    set_mtime(&dir.join("b.txt"), 3_000_000);
+    set_mtime(&dir.join("c.md"), 2_000_000);
+
+    let cases: &[(&[&str], [&str; 3])] = &[
+        (&[], ["a.zip", "b.txt", "c.md"]),
+        (&["-r"], ["c.md", "b.txt", "a.zip"]),
+        (&["-S"], ["a.zip", "c.md", "b.txt"]),
+        (&["-t"], ["b.txt", "c.md", "a.zip"]),
+        (&["-X"], ["c.md", "b.txt", "a.zip"]),
+        (&["-S", "-t"], ["b.txt", "c.md", "a.zip"]),
+        (&["-t", "-S"], ["a.zip", "c.md", "b.txt"]),
+        (&["--sort=size", "-t"], ["b.txt", "c.md", "a.zip"]),
+        (&["-t", "--sort=name"], ["a.zip", "b.txt", "c.md"]),
+        (&["-S", "-r"], ["b.txt", "c.md", "a.zip"]),
+        (&["-S", "--sort=extension"], ["c.md", "b.txt", "a.zip"]),
+    ];
+
+    for (args, expected) in cases {
+        assert_eq!(listing(&dir, args), expected, "lsr {:?}", args);
+    }
+}
+
+#[test]
+fn test_sort_time_implied_by_time_choice() {
+    let dir = fixture("sort_time_implied");
+    fs::write(dir.join("a_old"), "").unwrap();
+    fs::write(dir.join("b_new"), "").unwrap();
+    set_mtime(&dir.join("a_old"), 1_000_000);
+    set_mtime(&dir.join("b_new"), 2_000_000);
+
+    let mut cmd = Command::cargo_bin(PROG_NAME).unwrap();
+
+    // Set the command-line arguments and options
+    cmd.arg("-c").arg("mtime").arg(&dir);
+
+    // Run the command and check the output
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::is_match(r"^b_new .*\na_old .*\n$").unwrap());
+
+    // An explicit sort key takes precedence over the time choice.
+    let mut cmd = Command::cargo_bin(PROG_NAME).unwrap();
+    cmd.arg("-c").arg("mtime").arg("--sort=name").arg(&dir);
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::is_match(r"^a_old .*\nb_new .*\n$").unwrap());
+}
+
+#[test]
+fn test_sort_version() {
+    let dir = fixture("sort_version");
+    for name in ["file10", "file2", "file1"] {
+        fs::write(dir.join(name), "").unwrap();
+    }
+
+    assert_eq!(listing(&dir, &["-v"]), ["file1", "file2", "file10"]);
+    assert_eq!(listing(&dir, &[]), ["file1", "file10", "file2"]);
+}
+
+#[test]
+fn test_sort_reverse_unsorted_conflict() {
+    let mut cmd = Command::cargo_bin(PROG_NAME).unwrap();
+
+    // Set the command-line arguments and options
+    cmd.arg("-U").arg("-r").arg(".");
+
+    // Run the command and check the output
+    cmd.assert().failure().stderr(predicate::str::contains(
+        "cannot reverse an unsorted listing",
+    ));
+}
+
+#[cfg(unix)]
+#[test]
+fn test_control_chars_resolution() {
+    let dir = fixture("control_chars");
+    fs::write(dir.join("bell\u{7}name"), "").unwrap();
+
+    let cases: &[(&str, &[&str], &str)] = &[
+        ("1", &["--show-control-chars"], "bell\u{7}name\n"),
+        ("0", &["-q"], "bell?name\n"),
+        ("0", &["-q", "--show-control-chars"], "bell\u{7}name\n"),
+        ("1", &["--show-control-chars", "-q"], "bell?name\n"),
+        ("1", &["-b"], "bell\\007name\n"),
+        ("0", &["-b", "--show-control-chars"], "bell\\007name\n"),
+    ];
+
+    for (tty, args, expected) in cases {
+        let mut cmd = Command::cargo_bin(PROG_NAME).unwrap();
+
+        // Set the command-line arguments and options
+        cmd.env("LSR_FORCE_TTY", tty).args(*args).arg(&dir);
+
+        // Run the command and check the output
+        cmd.assert().success().stdout(*expected);
+    }
+}
+
+#[cfg(unix)]
+#[test]
+fn test_escape_styles() {
+    let dir = fixture("escape_styles");
+    fs::write(dir.join("bell\u{7}name"), "").unwrap();
+
+    let cases: &[(&[&str], &str)] = &[
+        (&["-b"], "bell\\007name\n"),
+        (&["--escape-style=octal"], "bell\\007name\n"),
+        (&["--escape-style=hex"], "bell\\x07name\n"),
+        (&["-b", "--escape-style=hex"], "bell\\x07name\n"),
+    ];
+
+    for (args, expected) in cases {
+        let mut cmd = Command::cargo_bin(PROG_NAME).unwrap();
+
+        // Set the command-line arguments and options
+        cmd.args(*args).arg("--verify-quoting").arg(&dir);
+
+        // Run the command and check the output
+        cmd.assert().success().stdout(*expected).stderr("");
+    }
+}
+
+#[test]
+fn test_commas_unlimited_width() {
+    let dir = fixture("commas_unlimited");
+    for index in 0..40 {
+        fs::write(dir.join(format!("file_{:02}", index)), "").unwrap();
+    }
+
+    let mut cmd = Command::cargo_bin(PROG_NAME).unwrap();
+
+    // Set the command-line arguments and options
+    cmd.arg("--width=0").arg("-m").arg(&dir);
+
+    // Run the command and check the output
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::is_match(r"^file_00, file_01, (file_\d\d, )+file_39\n$").unwrap());
+}
+
+#[test]
+fn test_commas_wraps_at_width() {
+    let dir = fixture("commas_wrap");
+    for name in ["aa", "bb", "cc", "dd"] {
+        fs::write(dir.join(name), "").unwrap();
+    }
+
+    let mut cmd = Command::cargo_bin(PROG_NAME).unwrap();
+
+    // Set the command-line arguments and options
+    cmd.arg("-w").arg("10").arg("--format=commas").arg(&dir);
+
+    // Run the command and check the output
+    cmd.assert().success().stdout("aa, bb,\ncc, dd\n");
+}
+
+/// Builds `name/{b, a/{x, y/{z}}, c.txt}` under a fresh fixture root.
+fn tree_fixture(name: &str) -> PathBuf {
+    let root = fixture(name);
+    let tree = root.join("tree");
+    fs::create_dir_all(tree.join("a").join("y")).unwrap();
+    fs::write(tree.join("b"), "").unwrap();
+    fs::write(tree.join("c.txt"), "").unwrap();
+    fs::write(tree.join("a").join("x"), "").unwrap();
+    fs::write(tree.join("a").join("y").join("z"), "").unwrap();
+    root
+}
+
+#[test]
+fn test_recursive_headers() {
+    let root = tree_fixture("recursive_headers");
+
+    let mut cmd = Command::cargo_bin(PROG_NAME).unwrap();
+
+    // Set the command-line arguments and options
+    cmd.current_dir(&root).arg("-R").arg("tree");
+
+    // Run the command and check the output
+    cmd.assert()
+        .success()
+        .stdout("tree:\na\nb\nc.txt\n\ntree/a:\nx\ny\n\ntree/a/y:\nz\n");
+}
+
+#[test]
+fn test_recursive_multiple_operands() {
+    let root = fixture("recursive_operands");
+    fs::create_dir_all(root.join("b/sub")).unwrap();
+    fs::create_dir(root.join("a")).unwrap();
+    for file in ["f2", "f1", "a/x", "b/y", "b/sub/z"] {
+        fs::write(root.join(file), "").unwrap();
+    }
+
+    let mut cmd = Command::cargo_bin(PROG_NAME).unwrap();
+
+    // Set the command-line arguments and options
+    cmd.current_dir(&root).args(["-R", "b", "f2", "a", "f1"]);
+
+    // Run the command and check the output
+    cmd.assert()
+        .success()
+        .stdout("f1\nf2\n\na:\nx\n\nb:\nsub\ny\n\nb/sub:\nz\n");
+
+    let mut plain = Command::cargo_bin(PROG_NAME).unwrap();
+    plain.current_dir(&root).args(["b", "missing", "a"]);
+    plain
+        .assert()
+        .code(1)
+        .stdout("a:\nx\n\nb:\nsub\ny\n")
+        .stderr("lsr: cannot access 'missing': No such file or directory\n");
+}
+
+#[test]
+fn test_directory_operands_follow_sort_order() {
+    let root = fixture("operand_sort_order");
+    fs::create_dir(root.join("early")).unwrap();
+    fs::create_dir(root.join("late")).unwrap();
+    fs::create_dir(root.join("zbig")).unwrap();
+    for i in 0..300 {
+        fs::write(root.join(format!("zbig/{:0>60}", i)), "").unwrap();
+    }
+    set_mtime(&root.join("early"), 1_000_000);
+    set_mtime(&root.join("late"), 2_000_000);
+
+    let mut cmd = Command::cargo_bin(PROG_NAME).unwrap();
+
+    // Set the command-line arguments and options
+    cmd.current_dir(&root).args(["-t", "early", "late"]);
+
+    // Run the command and check the output
+    cmd.assert().success().stdout("late:\n\nearly:\n");
+
+    let mut reversed = Command::cargo_bin(PROG_NAME).unwrap();
+    reversed
+        .current_dir(&root)
+        .args(["-t", "-r", "late", "early"]);
+    reversed.assert().success().stdout("early:\n\nlate:\n");
+
+    let mut by_size = Command::cargo_bin(PROG_NAME).unwrap();
+    by_size.current_dir(&root).args(["-S", "early", "zbig"]);
+    let output = by_size.output().unwrap();
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.starts_with("zbig:\n"), "{}", stdout);
+    assert!(stdout.ends_with("\n\nearly:\n"), "{}", stdout);
+}
+
+#[test]
+fn test_named_operands_skip_filters() {
+    let root = fixture("named_operands_filters");
+    fs::create_dir(root.join("dir")).unwrap();
+    fs::write(root.join("dir/debug.log"), "").unwrap();
+    fs::write(root.join("dir/notes.txt"), "").unwrap();
+    fs::write(root.join("dir/.lsrignore"), "notes.txt\n").unwrap();
+
+    let mut cmd = Command::cargo_bin(PROG_NAME).unwrap();
+
+    // Set the command-line arguments and options
+    cmd.current_dir(&root)
+        .args(["--exclude=*.log", "dir/debug.log", "dir/notes.txt"]);
+
+    // Run the command and check the output
+    cmd.assert()
+        .success()
+        .stdout("dir/debug.log\ndir/notes.txt\n");
+
+    let mut listed = Command::cargo_bin(PROG_NAME).unwrap();
+    listed.current_dir(&root).args(["--exclude=*.log", "dir"]);
+    listed.assert().success().stdout("");
+}
+
+#[cfg(unix)]
+#[test]
+fn test_recursive_matches_coreutils() {
+    let root = tree_fixture("recursive_coreutils");
+
+    let expected = match Command::new("ls")
+        .env("LC_ALL", "C")
+        .current_dir(&root)
+        .arg("-R")
+        .arg("tree")
+        .output()
+    {
+        Ok(output) if output.status.success() => output.stdout,
+        _ => return,
+    };
+
+    let mut cmd = Command::cargo_bin(PROG_NAME).unwrap();
+
+    // Set the command-line arguments and options
+    cmd.current_dir(&root).arg("-R").arg("tree");
+
+    // Run the command and check the output
+    cmd.assert().success().stdout(expected);
+}
+
+/// Output of GNU `ls` run with `args` in `dir`, or `None` when the system
+/// `ls` is missing or is not GNU's (BSD `ls` takes different flags).
+fn gnu_ls(dir: &std::path::Path, args: &[&str]) -> Option<String> {
+    let version = Command::new("ls").arg("--version").output().ok()?;
+    if !String::from_utf8_lossy(&version.stdout).contains("GNU coreutils") {
+        return None;
+    }
+    let output = Command::new("ls")
+        .env("LC_ALL", "C")
+        .env("COLUMNS", "80")
+        .current_dir(dir)
+        .args(args)
+        .output()
+        .ok()?;
+    Some(String::from_utf8(output.stdout).unwrap())
+}
+
+/// Irons out differences between GNU ls and lsr that are accepted by
+/// design: color sequences are dropped and GNU's tab padding in grids is
+/// expanded to spaces.
+fn normalize_listing(output: &str) -> String {
+    let mut plain = String::new();
+    let mut rest = output;
+    while let Some(start) = rest.find('\x1b') {
+        plain.push_str(&rest[..start]);
+        rest = rest[start..]
+            .find('m')
+            .map_or("", |end| &rest[start + end + 1..]);
+    }
+    plain.push_str(rest);
+
+    let mut normalized = String::new();
+    for line in plain.lines() {
+        let mut expanded = String::new();
+        for c in line.chars() {
+            if c == '\t' {
+                let stop = (expanded.chars().count() / 8 + 1) * 8;
+                while expanded.chars().count() < stop {
+                    expanded.push(' ');
+                }
+            } else {
+                expanded.push(c);
+            }
+        }
+        normalized.push_str(&expanded);
+        normalized.push('\n');
+    }
+    normalized
+}
+
+/// Runs lsr and GNU ls side by side over a mixed fixture for each flag set
+/// both implement. Known divergences are left out: `-a` (lsr does not list
+/// `.` and `..`), `-b` (lsr escapes spaces) and `-d`, `-i`, `-n` and `-Q`,
+/// which mean something else or nothing to lsr.
+#[cfg(unix)]
+#[test]
+fn test_compare_with_gnu_ls() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let dir = fixture("compare_with_ls");
+    fs::create_dir_all(dir.join("sub/deeper")).unwrap();
+    fs::create_dir(dir.join("with space")).unwrap();
+    fs::write(dir.join("a.txt"), "hi\n").unwrap();
+    fs::write(dir.join("big.bin"), vec![0; 5000]).unwrap();
+    fs::write(dir.join(".hidden"), "").unwrap();
+    fs::write(dir.join("B_upper"), "").unwrap();
+    fs::write(dir.join("file10"), "").unwrap();
+    fs::write(dir.join("file9.tar.gz"), "x").unwrap();
+    fs::write(dir.join("sub/inner"), "x").unwrap();
+    fs::write(dir.join("sub/deeper/z"), "").unwrap();
+    fs::write(dir.join("run.sh"), "#!/bin/sh\n").unwrap();
+    fs::set_permissions(dir.join("run.sh"), fs::Permissions::from_mode(0o755)).unwrap();
+    std::os::unix::fs::symlink("a.txt", dir.join("link")).unwrap();
+    std::os::unix::fs::symlink("missing", dir.join("dangling")).unwrap();
+    // Distinct mtimes keep the time sorts free of ties.
+    let files = [
+        "a.txt",
+        "big.bin",
+        ".hidden",
+        "B_upper",
+        "file10",
+        "file9.tar.gz",
+        "run.sh",
+        "sub",
+        "with space",
+    ];
+    for (index, name) in files.iter().enumerate() {
+        set_mtime(&dir.join(name), 1_600_000_000 + index as u64 * 1000);
+    }
+
+    let cases: &[&[&str]] = &[
+        &[],
+        &["-A"],
+        &["-r"],
+        &["-S"],
+        &["-t"],
+        &["-rt"],
+        &["-X"],
+        &["-v"],
+        &["-1"],
+        &["-C"],
+        &["-x"],
+        &["-m"],
+        &["-p"],
+        &["-F"],
+        &["-RF"],
+        &["-mF"],
+        &["-CF"],
+        &["-xF", "-w", "30"],
+        &["-s"],
+        &["-sh"],
+        &["-q"],
+        &["-R"],
+        &["-RA", "-r"],
+        &["-C", "-w", "40"],
+        &["-x", "-w", "40"],
+        &["--color=always"],
+    ];
+    for args in cases {
+        let Some(expected) = gnu_ls(&dir, args) else {
+            return;
+        };
+        let output = Command::cargo_bin(PROG_NAME)
+            .unwrap()
+            .env("COLUMNS", "80")
+            .current_dir(&dir)
+            .args(*args)
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "lsr {:?} failed", args);
+        assert_eq!(
+            normalize_listing(&String::from_utf8(output.stdout).unwrap()),
+            normalize_listing(&expected),
+            "lsr {:?} differs from GNU ls",
+            args
+        );
+    }
+}
+
+#[cfg(unix)]
+#[test]
+fn test_stat() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let dir = fixture("stat");
+    let file = dir.join("notes.txt");
+    fs::write(&file, "hello").unwrap();
+    fs::set_permissions(&file, fs::Permissions::from_mode(0o644)).unwrap();
+
+    let mut cmd = Command::cargo_bin(PROG_NAME).unwrap();
+
+    // Set the command-line arguments and options
+    cmd.arg("--stat").arg(&file);
+
+    // Run the command and check the output
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("notes.txt"))
+        .stdout(predicate::str::contains("  Type: regular file\n"))
+        .stdout(predicate::str::is_match(r"(?m)^  Size: 5 ").unwrap())
+        .stdout(predicate::str::contains("(0644/-rw-r--r--)"))
+        .stdout(
+            predicate::str::is_match(r"(?m)^Modify: \d{4}-\d\d-\d\d \d\d:\d\d:\d\d\.\d{9} ")
+                .unwrap(),
+        );
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn test_stat_marks_acl() {
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+
+    let dir = fixture("stat_acl");
+    let file = dir.join("shared.txt");
+    fs::write(&file, "").unwrap();
+
+    // An access ACL granting uid 65534 read, as `setfacl -m u:65534:r`
+    // stores it: version 2, then (tag, perm, id) entries.
+    let mut acl = 2u32.to_le_bytes().to_vec();
+    for (tag, perm, id) in [
+        (0x01u16, 6u16, u32::MAX),
+        (0x02, 4, 65534),
+        (0x04, 4, u32::MAX),
+        (0x10, 4, u32::MAX),
+        (0x20, 4, u32::MAX),
+    ] {
+        acl.extend(tag.to_le_bytes());
+        acl.extend(perm.to_le_bytes());
+        acl.extend(id.to_le_bytes());
+    }
+    let path = CString::new(file.as_os_str().as_bytes()).unwrap();
+    let set = unsafe {
+        libc::setxattr(
+            path.as_ptr(),
+            c"system.posix_acl_access".as_ptr(),
+            acl.as_ptr().cast(),
+            acl.len(),
+            0,
+        )
+    };
+    if set != 0 {
+        // The filesystem does not support ACLs.
+        return;
+    }
+
+    let mut cmd = Command::cargo_bin(PROG_NAME).unwrap();
+
+    // Set the command-line arguments and options
+    cmd.arg("--stat").arg(&file);
+
+    // Run the command and check the output
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("/-rw-r--r--+)"));
+}
+
+#[test]
+fn test_stat_rejects_multiple_paths() {
+    let mut cmd = Command::cargo_bin(PROG_NAME).unwrap();
+
+    // Set the command-line arguments and options
+    cmd.arg("--stat").arg("Cargo.toml").arg("README.md");
+
+    // Run the command and check the output
+    cmd.assert().failure();
+}
+
+/// Builds a tree `depth` levels deep with `fanout` directories and files
+/// per level.
+fn deep_tree(name: &str, depth: usize, fanout: usize) -> PathBuf {
+    fn fill(dir: &std::path::Path, depth: usize, fanout: usize) {
+        for index in 0..fanout {
+            fs::write(dir.join(format!("file{}", index)), "").unwrap();
+            if depth > 0 {
+                let sub = dir.join(format!("dir{}", index));
+                fs::create_dir(&sub).unwrap();
+                fill(&sub, depth - 1, fanout);
+            }
+        }
+    }
+
+    let root = fixture(name);
+    fill(&root, depth, fanout);
+    root
+}
+
+#[test]
+fn test_parallel_recursion_matches_serial() {
+    let root = deep_tree("parallel_matches_serial", 3, 4);
+
+    let serial = Command::cargo_bin(PROG_NAME)
+        .unwrap()
+        .arg("-R")
+        .arg(&root)
+        .output()
+        .unwrap();
+
+    let mut cmd = Command::cargo_bin(PROG_NAME).unwrap();
+
+    // Set the command-line arguments and options
+    cmd.arg("-R").arg("--parallel").arg(&root);
+
+    // Run the command and check the output
+    cmd.assert().success().stdout(serial.stdout);
+}
+
+#[test]
+fn test_parallel_recursion_keeps_groups_intact() {
+    let root = deep_tree("parallel_groups_intact", 3, 5);
+
+    for _ in 0..5 {
+        let output = Command::cargo_bin(PROG_NAME)
+            .unwrap()
+            .arg("-R")
+            .arg("--parallel")
+            .arg(&root)
+            .output()
+            .unwrap();
+        assert!(output.status.success());
+
+        // Every group is a header followed by exactly that directory's entries.
+        let stdout = String::from_utf8(output.stdout).unwrap();
+        let groups: Vec<&str> = stdout.split("\n\n").collect();
+        assert_eq!(groups.len(), 1 + 5 + 25 + 125);
+        for group in groups {
+            let mut lines = group.lines();
+            let header = lines.next().unwrap();
+            let dir = header.strip_suffix(':').expect(header);
+            let mut expected: Vec<String> = fs::read_dir(dir)
+                .unwrap()
+                .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+                .collect();
+            expected.sort();
+            assert_eq!(lines.collect::<Vec<_>>(), expected, "group {}", dir);
+        }
+    }
+}
+
+/// Timing comparison rather than a correctness check; run with
+/// `cargo test -- --ignored --nocapture bench_parallel_recursion`.
+#[test]
+#[ignore]
+fn bench_parallel_recursion() {
+    let root = deep_tree("bench_parallel_recursion", 5, 6);
+
+    for flags in [&["-R"][..], &["-R", "--parallel"][..]] {
+        let start = std::time::Instant::now();
+        Command::cargo_bin(PROG_NAME)
+            .unwrap()
+            .args(flags)
+            .arg(&root)
+            .output()
+            .unwrap();
+        println!("lsr {:?}: {:?}", flags, start.elapsed());
+    }
+}
+
+#[test]
+fn test_low_memory_matches_default() {
+    let root = deep_tree("low_memory_matches", 2, 3);
+    let full = listing(&root, &["-R", "--size-by-type"]);
+
+    assert_eq!(
+        listing(
+            &root,
+            &["-R", "--parallel", "--low-memory", "--size-by-type"]
+        ),
+        full
+    );
+}
+
+/// Peak resident memory of `--parallel` against `--low-memory`, sampled
+/// from /proc while lsr runs; run with
+/// `cargo test -- --ignored --nocapture bench_low_memory_rss`. Raise the
+/// fanout to 10 for a tree of about a million files.
+#[cfg(target_os = "linux")]
+#[test]
+#[ignore]
+fn bench_low_memory_rss() {
+    let root = deep_tree("bench_low_memory_rss", 5, 6);
+
+    for flags in [
+        &["-R", "--parallel"][..],
+        &["-R", "--parallel", "--low-memory"][..],
+    ] {
+        let mut child = std::process::Command::new(assert_cmd::cargo::cargo_bin(PROG_NAME))
+            .args(flags)
+            .arg(&root)
+            .stdout(std::process::Stdio::null())
+            .spawn()
+            .unwrap();
+        let status = format!("/proc/{}/status", child.id());
+        let mut peak = String::from("unknown");
+        while child.try_wait().unwrap().is_none() {
+            if let Some(line) = fs::read_to_string(&status).ok().and_then(|s| {
+                s.lines()
+                    .find(|l| l.starts_with("VmHWM:"))
+                    .map(String::from)
+            }) {
+                peak = line.trim_start_matches("VmHWM:").trim().to_string();
+            }
+        }
+        println!("lsr {:?}: peak RSS {}", flags, peak);
+    }
+}
+
+#[test]
+fn test_size_sort_uses_raw_bytes_with_human() {
+    let dir = fixture("size_sort_human");
+    // Both render as "1.1K" with -h.
+    fs::write(dir.join("a_small"), "x".repeat(1025)).unwrap();
+    fs::write(dir.join("b_large"), "x".repeat(1100)).unwrap();
+
+    assert_eq!(listing(&dir, &["-S", "-h"]), ["b_large", "a_small"]);
+    assert_eq!(listing(&dir, &["-S", "-h", "-r"]), ["a_small", "b_large"]);
+
+    let mut cmd = Command::cargo_bin(PROG_NAME).unwrap();
+
+    // Set the command-line arguments and options
+    cmd.arg("-h").arg("--size-by-type").arg(&dir);
+
+    // Run the command and check the output
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::is_match(r"(?m)^files\s+2\s+2\.1K$").unwrap());
+}
+
+#[test]
+fn test_min_and_max_depth() {
+    let root = tree_fixture("min_max_depth");
+
+    let cases: &[(&[&str], &str)] = &[
+        (&["--max-depth=1"], "tree:\na\nb\nc.txt\n"),
+        (&["--max-depth=2"], "tree:\na\nb\nc.txt\n\ntree/a:\nx\ny\n"),
+        (&["--min-depth=2", "--max-depth=2"], "tree/a:\nx\ny\n"),
+        (&["--min-depth=3"], "tree/a/y:\nz\n"),
+        (
+            &["--min-depth=2", "--parallel"],
+            "tree/a:\nx\ny\n\ntree/a/y:\nz\n",
+        ),
+    ];
+
+    for (args, expected) in cases {
+        let mut cmd = Command::cargo_bin(PROG_NAME).unwrap();
+
+        // Set the command-line arguments and options
+        cmd.current_dir(&root).arg("-R").args(*args).arg("tree");
+
+        // Run the command and check the output
+        cmd.assert().success().stdout(*expected);
+    }
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn test_device_id_column() {
+    let dir = fixture("device_id");
+    fs::write(dir.join("file"), "").unwrap();
+    fs::write(dir.join("other"), "").unwrap();
+
+    let mut cmd = Command::cargo_bin(PROG_NAME).unwrap();
+
+    // Set the command-line arguments and options
+    cmd.arg("--device-id").arg("-s").arg(&dir);
+
+    // Run the command and check the output
+    cmd.assert().success().stdout(
+        predicate::str::is_match(r"^total 0\n(\d+:\d+) 0 file\n(\d+:\d+) 0 other\n$").unwrap(),
+    );
+}
+
+#[test]
+fn test_newest_and_oldest() {
+    let dir = fixture("newest_oldest");
+    for (name, mtime) in [
+        ("a.log", 2_000_000),
+        ("b.log", 3_000_000),
+        ("c.txt", 1_000_000),
+    ] {
+        fs::write(dir.join(name), "").unwrap();
+        set_mtime(&dir.join(name), mtime);
+    }
+
+    assert_eq!(listing(&dir, &["--newest"]), ["b.log"]);
+    assert_eq!(listing(&dir, &["--oldest"]), ["c.txt"]);
+    // Filters apply before the pick.
+    assert_eq!(
+        listing(&dir, &["--oldest", "--hide-extension", "-X"]),
+        ["c"]
+    );
+
+    let mut cmd = Command::cargo_bin(PROG_NAME).unwrap();
+
+    // Set the command-line arguments and options
+    cmd.arg("--newest").arg("--oldest").arg(&dir);
+
+    // Run the command and check the output
+    cmd.assert().failure();
+}
+
+#[test]
+fn test_zero_terminated() {
+    let dir = fixture("zero_terminated");
+    fs::write(dir.join("a b"), "").unwrap();
+    fs::write(dir.join("line\nbreak"), "").unwrap();
+
+    let mut cmd = Command::cargo_bin(PROG_NAME).unwrap();
+
+    // Set the command-line arguments and options
+    cmd.env("LSR_FORCE_TTY", "1").arg("--zero").arg(&dir);
+
+    // Run the command and check the output
+    cmd.assert().success().stdout("a b\0line\nbreak\0");
+}
+
+#[test]
+fn test_zero_terminated_fields() {
+    let dir = fixture("zero_terminated_fields");
+    fs::write(dir.join("a"), "").unwrap();
+    fs::write(dir.join("b"), "").unwrap();
+
+    let mut cmd = Command::cargo_bin(PROG_NAME).unwrap();
+
+    // Set the command-line arguments and options
+    cmd.arg("--zero").arg("-s").arg(&dir);
+
+    // Run the command and check the output
+    cmd.assert()
+        .success()
+        .stdout(concat!("total 0\0", "0\0a\0\0", "0\0b\0\0"));
+}
+
+#[cfg(unix)]
+#[test]
+fn test_mark_mounts() {
+    use std::os::unix::fs::MetadataExt;
+
+    // /proc is a separate filesystem on any Linux system; skip elsewhere.
+    match (fs::metadata("/"), fs::metadata("/proc")) {
+        (Ok(root), Ok(proc)) if root.dev() != proc.dev() => {}
+        _ => return,
+    }
+
+    let mut cmd = Command::cargo_bin(PROG_NAME).unwrap();
+
+    // Set the command-line arguments and options
+    cmd.arg("--mark-mounts").arg("/");
+
+    // Run the command and check the output
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::is_match(r"(?m)^proc \[mount\]$").unwrap());
+
+    let dir = fixture("mark_mounts");
+    fs::create_dir(dir.join("plain")).unwrap();
+    let mut cmd = Command::cargo_bin(PROG_NAME).unwrap();
+    cmd.arg("--mark-mounts").arg(&dir);
+    cmd.assert().success().stdout("plain\n");
+}
+
+#[cfg(unix)]
+#[test]
+fn test_sanitize_escape_sequences() {
+    let dir = fixture("sanitize_names");
+    fs::write(dir.join("red\u{1b}[31mname"), "").unwrap();
+    fs::write(dir.join("title\u{1b}]0;pwned\u{7}"), "").unwrap();
+
+    let cases: &[(&str, &[&str], &str)] = &[
+        (
+            "1",
+            &["--show-control-chars"],
+            "red\\e[31mname\ntitle\\e]0;pwned\\a\n",
+        ),
+        (
+            "0",
+            &["--sanitize-names"],
+            "red\\e[31mname\ntitle\\e]0;pwned\\a\n",
+        ),
+        ("0", &[], "red\u{1b}[31mname\ntitle\u{1b}]0;pwned\u{7}\n"),
+        (
+            "1",
+            &["--show-control-chars", "--raw-names"],
+            "red\u{1b}[31mname\ntitle\u{1b}]0;pwned\u{7}\n",
+        ),
+    ];
+
+    for (tty, args, expected) in cases {
+        let mut cmd = Command::cargo_bin(PROG_NAME).unwrap();
+
+        // Set the command-line arguments and options
+        cmd.env("LSR_FORCE_TTY", tty)
+            .arg("-1")
+            .args(*args)
+            .arg(&dir);
+
+        // Run the command and check the output
+        cmd.assert().success().stdout(*expected);
+    }
+}
+
+#[test]
+fn test_size_thresholds() {
+    let dir = fixture("size_thresholds");
+    for (name, len) in [
+        ("a_999", 999),
+        ("b_1000", 1000),
+        ("c_1024", 1024),
+        ("d_1025", 1025),
+    ] {
+        fs::write(dir.join(name), vec![b'x'; len]).unwrap();
+    }
+
+    // Both bounds are exclusive; K is 1024 and KB is 1000.
+    assert_eq!(listing(&dir, &["--larger-than=1K"]), ["d_1025"]);
+    assert_eq!(listing(&dir, &["--smaller-than=1KB"]), ["a_999"]);
+    assert_eq!(
+        listing(&dir, &["--larger-than=999", "--smaller-than=1.0KiB"]),
+        ["b_1000"]
+    );
+
+    let mut cmd = Command::cargo_bin(PROG_NAME).unwrap();
+
+    // Set the command-line arguments and options
+    cmd.arg("--larger-than=10Q").arg(&dir);
+
+    // Run the command and check the output
+    cmd.assert()
+        .failure()
+        .stderr(predicate::str::contains("invalid size '10Q'"));
+}
+
+#[test]
+fn test_files_from() {
+    let dir = fixture("files_from");
+    fs::create_dir_all(dir.join("sub")).unwrap();
+    fs::write(dir.join("a.txt"), "").unwrap();
+    fs::write(dir.join("sub/b.txt"), "").unwrap();
+    fs::write(dir.join("sub/.hidden"), "").unwrap();
+    fs::write(dir.join("unlisted"), "").unwrap();
+    fs::write(
+        dir.join("lines"),
+        "sub/b.txt\nmissing\na.txt\nsub/.hidden\n",
+    )
+    .unwrap();
+    fs::write(dir.join("nul"), "sub/b.txt\0a.txt\0").unwrap();
+
+    let mut cmd = Command::cargo_bin(PROG_NAME).unwrap();
+
+    // Set the command-line arguments and options
+    cmd.current_dir(&dir).arg("--files-from=lines");
+
+    // Run the command and check the output
+    cmd.assert()
+        .code(1)
+        .stdout("a.txt\nsub/.hidden\nsub/b.txt\n")
+        .stderr("lsr: cannot access 'missing': No such file or directory\n");
+
+    let mut nul = Command::cargo_bin(PROG_NAME).unwrap();
+    nul.current_dir(&dir).args(["--files-from=nul", "-r"]);
+    nul.assert().success().stdout("sub/b.txt\na.txt\n");
+
+    let mut capped = Command::cargo_bin(PROG_NAME).unwrap();
+    capped
+        .current_dir(&dir)
+        .args(["--files-from=nul", "--max-total-entries=1"]);
+    capped.assert().code(4).stdout("a.txt\n");
+
+    set_mtime(&dir.join("a.txt"), 1_000_000);
+    set_mtime(&dir.join("sub/b.txt"), 2_000_000);
+    let mut newest = Command::cargo_bin(PROG_NAME).unwrap();
+    newest
+        .current_dir(&dir)
+        .args(["--files-from=nul", "--newest"]);
+    newest.assert().success().stdout("sub/b.txt\n");
+}
+
+#[test]
+fn test_file_size_column_alignment() {
+    let dir = fixture("file_size_column");
+    fs::write(dir.join("a_tiny"), "12345").unwrap();
+    fs::write(dir.join("b_kilo"), vec![b'x'; 2048]).unwrap();
+    let big = fs::File::create(dir.join("c_mega")).unwrap();
+    big.set_len(3 * 1024 * 1024).unwrap();
+
+    assert_eq!(
+        listing(&dir, &["--file-size", "-h"]),
+        ["   5 a_tiny", "2.0K b_kilo", "3.0M c_mega"]
+    );
+    assert_eq!(
+        listing(&dir, &["--file-size"]),
+        ["      5 a_tiny", "   2048 b_kilo", "3145728 c_mega"]
+    );
+}
+
+#[test]
+fn test_percent_of_total_size() {
+    let dir = fixture("percent_of_total");
+    fs::write(dir.join("a"), vec![b'x'; 100]).unwrap();
+    fs::write(dir.join("b"), vec![b'x'; 300]).unwrap();
+    fs::write(dir.join("c"), vec![b'x'; 200]).unwrap();
+    fs::write(dir.join("d"), vec![b'x'; 7]).unwrap();
+
+    let lines = listing(
+        &dir,
+        &["-1", "--sort=size", "--percent", "--file-size", "-h"],
+    );
+    assert_eq!(
+        lines,
+        [
+            "300 b  (49.4%)",
+            "200 c  (32.9%)",
+            "100 a  (16.5%)",
+            "  7 d  (1.2%)"
+        ]
+    );
+
+    let sum: f64 = lines
+        .iter()
+        .map(|line| {
+            let share = line.rsplit('(').next().unwrap();
+            share.trim_end_matches("%)").parse::<f64>().unwrap()
+        })
+        .sum();
+    assert!((sum - 100.0).abs() < 0.5, "percentages sum to {}", sum);
+}
+
+#[test]
+fn test_depth_zero_lists_self() {
+    let dir = fixture("depth_zero_self");
+    fs::create_dir_all(dir.join("somedir/inner")).unwrap();
+
+    let mut cmd = Command::cargo_bin(PROG_NAME).unwrap();
+
+    // Set the command-line arguments and options
+    cmd.current_dir(&dir).args(["-d0", "-F", "somedir"]);
+
+    // Run the command and check the output
+    cmd.assert().success().stdout("somedir/\n");
+
+    let mut bare = Command::cargo_bin(PROG_NAME).unwrap();
+    bare.current_dir(&dir)
+        .args(["--max-depth=0", "-F", "--no-self-indicator", "somedir"]);
+    bare.assert().success().stdout("somedir\n");
+
+    // `.` and `..` are shown as given, not as the paths they resolve to.
+    let mut dots = Command::cargo_bin(PROG_NAME).unwrap();
+    dots.current_dir(dir.join("somedir"))
+        .args(["-d0", "-F", ".", ".."]);
+    dots.assert().success().stdout("./\n../\n");
+
+    let mut parent = Command::cargo_bin(PROG_NAME).unwrap();
+    parent
+        .current_dir(dir.join("somedir"))
+        .args(["--max-depth=0", ".."]);
+    parent.assert().success().stdout("..\n");
+}
+
+#[test]
+fn test_extension_sort_keys() {
+    let dir = fixture("extension_sort_keys");
+    for name in ["c.txt", "Makefile", "d.Gz", "b.TXT", "a.tar.gz", ".bashrc"] {
+        fs::write(dir.join(name), "").unwrap();
+    }
+
+    // No extension first, then by lowercased last extension, then by name.
+    assert_eq!(
+        listing(&dir, &["-a", "-X"]),
+        [".bashrc", "Makefile", "a.tar.gz", "d.Gz", "b.TXT", "c.txt"]
+    );
+}
+
+#[cfg(unix)]
+#[test]
+fn test_symlink_filters() {
+    let dir = fixture("symlink_filters");
+    fs::create_dir(dir.join("sub")).unwrap();
+    fs::write(dir.join("target.txt"), "").unwrap();
+    std::os::unix::fs::symlink("target.txt", dir.join("good")).unwrap();
+    std::os::unix::fs::symlink("nowhere", dir.join("sub/bad")).unwrap();
+
+    assert_eq!(listing(&dir, &["--symlinks-only"]), ["good"]);
+    assert_eq!(listing(&dir, &["--broken-symlinks"]), Vec::<String>::new());
+
+    let mut cmd = Command::cargo_bin(PROG_NAME).unwrap();
+
+    // Set the command-line arguments and options
+    cmd.current_dir(&dir).args(["-R", "--broken-symlinks", "."]);
+
+    // Run the command and check the output
+    cmd.assert().success().stdout(".:\n\n./sub:\nbad\n");
+}
+
+#[test]
+fn test_recursive_group_order_follows_sort() {
+    let dir = fixture("recursive_group_order");
+    for (name, mtime) in [("a_old", 1_500_000_000), ("b_new", 1_600_000_000)] {
+        fs::create_dir(dir.join(name)).unwrap();
+        fs::write(dir.join(name).join("inside"), "").unwrap();
+        set_mtime(&dir.join(name), mtime);
+    }
+
+    let headers = |args: &[&str]| -> Vec<String> {
+        let mut all = vec!["-R"];
+        all.extend(args);
+        listing(&dir, &all)
+            .into_iter()
+            .filter(|line| line.ends_with(':'))
+            .map(|line| line.rsplit('/').next().unwrap().to_string())
+            .collect()
+    };
+
+    let root = format!("{}:", dir.file_name().unwrap().to_string_lossy());
+    assert_eq!(headers(&[]), [root.as_str(), "a_old:", "b_new:"]);
+    assert_eq!(headers(&["-t"]), [root.as_str(), "b_new:", "a_old:"]);
+    assert_eq!(headers(&["-t", "-r"]), [root.as_str(), "a_old:", "b_new:"]);
+    assert_eq!(headers(&["-r"]), [root.as_str(), "b_new:", "a_old:"]);
+}
+
+#[cfg(unix)]
+#[test]
+fn test_escape_round_trip_verification() {
+    use std::ffi::OsStr;
+    use std::os::unix::ffi::OsStrExt;
+
+    let dir = fixture("escape_round_trip");
+    fs::write(dir.join("back\\slash"), "").unwrap();
+    fs::write(dir.join(OsStr::from_bytes(b"bad\xff")), "").unwrap();
+    fs::write(dir.join("café"), "").unwrap();
+    fs::write(dir.join("tab\there"), "").unwrap();
+
+    let mut cmd = Command::cargo_bin(PROG_NAME).unwrap();
+
+    // Set the command-line arguments and options
+    cmd.args(["-b", "--verify-quoting"]).arg(&dir);
+
+    // Run the command and check the output
+    cmd.assert()
+        .success()
+        .stdout("back\\\\slash\nbad\\377\ncafé\ntab\\011here\n")
+        .stderr("");
+}
+
+#[test]
+fn test_rollup() {
+    let dir = fixture("rollup");
+    fs::create_dir_all(dir.join("a/y")).unwrap();
+    fs::create_dir(dir.join("b")).unwrap();
+    fs::write(dir.join("a/x"), vec![b'x'; 100]).unwrap();
+    fs::write(dir.join("a/y/z"), vec![b'x'; 50]).unwrap();
+    fs::write(dir.join("b/w"), vec![b'x'; 10]).unwrap();
+    fs::write(dir.join("top"), "x").unwrap();
+
+    let mut cmd = Command::cargo_bin(PROG_NAME).unwrap();
+
+    // Set the command-line arguments and options
+    cmd.current_dir(&dir).args(["--rollup", "."]);
+
+    // Run the command and check the output
+    cmd.assert()
+        .success()
+        .stdout("161\t.\n150\t./a\n50\t./a/y\n10\t./b\n");
+
+    let mut shallow = Command::cargo_bin(PROG_NAME).unwrap();
+    shallow
+        .current_dir(&dir)
+        .args(["--rollup", "--max-depth=1", "."]);
+    shallow
+        .assert()
+        .success()
+        .stdout("161\t.\n150\t./a\n10\t./b\n");
+}
+
+#[cfg(unix)]
+#[test]
+fn test_rollup_hard_links() {
+    let dir = fixture("rollup_hard_links");
+    fs::create_dir_all(dir.join("a/b")).unwrap();
+    fs::write(dir.join("a/data"), vec![b'x'; 100]).unwrap();
+    fs::hard_link(dir.join("a/data"), dir.join("a/copy")).unwrap();
+    fs::write(dir.join("a/b/own"), vec![b'x'; 10]).unwrap();
+
+    let mut cmd = Command::cargo_bin(PROG_NAME).unwrap();
+
+    // Set the command-line arguments and options
+    cmd.current_dir(&dir).args(["--rollup", "."]);
+
+    // Run the command and check the output
+    cmd.assert()
+        .success()
+        .stdout("110\t.\n110\t./a\n10\t./a/b\n");
+}
+
+#[cfg(unix)]
+#[test]
+fn test_rollup_symlinks() {
+    let dir = fixture("rollup_symlinks");
+    fs::create_dir_all(dir.join("tree/sub")).unwrap();
+    fs::create_dir(dir.join("other")).unwrap();
+    fs::write(dir.join("tree/sub/file"), vec![b'x'; 100]).unwrap();
+    fs::write(dir.join("other/big"), vec![b'x'; 5000]).unwrap();
+    // Links count as the length of their target path: 11 and 2 bytes.
+    std::os::unix::fs::symlink("../../other", dir.join("tree/sub/elsewhere")).unwrap();
+    std::os::unix::fs::symlink("..", dir.join("tree/sub/up")).unwrap();
+
+    let mut cmd = Command::cargo_bin(PROG_NAME).unwrap();
+
+    // Set the command-line arguments and options
+    cmd.current_dir(&dir).args(["--rollup", "tree"]);
+
+    // Run the command and check the output
+    cmd.assert().success().stdout("113\ttree\n113\ttree/sub\n");
+
+    let mut follow = Command::cargo_bin(PROG_NAME).unwrap();
+    follow
+        .current_dir(&dir)
+        .args(["--rollup", "--rollup-follow-symlinks", "tree"]);
+    // `up` leads back to `tree`, which is never counted twice.
+    follow
+        .assert()
+        .success()
+        .stdout("5100\ttree\n5100\ttree/sub\n5000\ttree/sub/elsewhere\n");
+}
+
+#[cfg(unix)]
+#[test]
+fn test_dereference_special_files() {
+    let dir = fixture("dereference_special");
+    let status = std::process::Command::new("mkfifo")
+        .arg(dir.join("fifo"))
+        .status()
+        .unwrap();
+    assert!(status.success());
+    let _socket = std::os::unix::net::UnixListener::bind(dir.join("sock")).unwrap();
+    std::os::unix::fs::symlink("fifo", dir.join("to_fifo")).unwrap();
+    std::os::unix::fs::symlink("sock", dir.join("to_sock")).unwrap();
+    std::os::unix::fs::symlink("missing", dir.join("to_nothing")).unwrap();
+
+    assert_eq!(
+        listing(&dir, &["-F"]),
+        ["fifo|", "sock=", "to_fifo@", "to_nothing@", "to_sock@"]
+    );
+    assert_eq!(
+        listing(&dir, &["-F", "-L"]),
+        ["fifo|", "sock=", "to_fifo|", "to_nothing@", "to_sock="]
+    );
+}
+
+#[cfg(unix)]
+#[test]
+fn test_recursive_skips_unreadable_directory() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let dir = fixture("recursive_unreadable");
+    fs::create_dir(dir.join("a_locked")).unwrap();
+    fs::create_dir(dir.join("b_open")).unwrap();
+    fs::write(dir.join("b_open/file"), "").unwrap();
+    let locked = dir.join("a_locked");
+    fs::set_permissions(&locked, fs::Permissions::from_mode(0o000)).unwrap();
+    // Permissions do not stop root.
+    let readable = fs::read_dir(&locked).is_ok();
+
+    for flags in [&["-R"][..], &["-R", "--parallel"][..]] {
+        let mut cmd = Command::cargo_bin(PROG_NAME).unwrap();
+
+        // Set the command-line arguments and options
+        cmd.current_dir(&dir).args(flags).arg(".");
+
+        // Run the command and check the output
+        if readable {
+            cmd.assert().success();
+        } else {
+            cmd.assert()
+                .code(1)
+                .stdout(".:\na_locked\nb_open\n\n./b_open:\nfile\n")
+                .stderr("lsr: cannot open directory './a_locked': Permission denied\n");
+        }
+    }
+
+    fs::set_permissions(&locked, fs::Permissions::from_mode(0o755)).unwrap();
+}
+
+#[cfg(unix)]
+#[test]
+fn test_dereference_recursion_stops_at_cycle() {
+    let dir = fixture("dereference_cycle");
+    fs::create_dir(dir.join("sub")).unwrap();
+    fs::write(dir.join("sub/file"), "").unwrap();
+    std::os::unix::fs::symlink("..", dir.join("sub/up")).unwrap();
+
+    for flags in [&["-R", "-L"][..], &["-R", "-L", "--parallel"][..]] {
+        let mut cmd = Command::cargo_bin(PROG_NAME).unwrap();
+
+        // Set the command-line arguments and options
+        cmd.current_dir(&dir).args(flags).arg(".");
+
+        // Run the command and check the output
+        cmd.assert()
+            .code(1)
+            .stdout(".:\nsub\n\n./sub:\nfile\nup\n")
+            .stderr("lsr: './sub/up': not listing already-listed directory\n");
+    }
+}
+
+#[cfg(unix)]
+#[test]
+fn test_recursion_ignores_substituted_symlink() {
+    use std::io::Read;
+    use std::process::Stdio;
+
+    let dir = fixture("substituted_symlink");
+    fs::create_dir_all(dir.join("tree/sub")).unwrap();
+    fs::create_dir(dir.join("secret")).unwrap();
+    fs::write(dir.join("secret/private"), "").unwrap();
+    // Enough names that the root group overflows the pipe, so lsr is still
+    // writing it, before descending into `sub`, when the swap happens.
+    for index in 0..4000 {
+        let name = format!("{:060}", index);
+        fs::write(dir.join("tree").join(name), "").unwrap();
+    }
+
+    let mut child = Command::cargo_bin(PROG_NAME)
+        .unwrap()
+        .current_dir(dir.join("tree"))
+        .args(["-R", "."])
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let mut stdout = child.stdout.take().unwrap();
+    let mut first = [0; 1];
+    stdout.read_exact(&mut first).unwrap();
+
+    fs::rename(dir.join("tree/sub"), dir.join("moved")).unwrap();
+    std::os::unix::fs::symlink("../secret", dir.join("tree/sub")).unwrap();
+
+    let mut rest = String::new();
+    stdout.read_to_string(&mut rest).unwrap();
+    let output = child.wait_with_output().unwrap();
+    assert_eq!(output.status.code(), Some(1));
+    assert!(
+        !rest.contains("private"),
+        "followed the substituted symlink"
+    );
+    assert_eq!(
+        String::from_utf8(output.stderr).unwrap(),
+        "lsr: cannot open directory './sub': Not a directory\n"
+    );
+}
+
+#[cfg(unix)]
+#[test]
+fn test_recursion_ignores_substituted_ancestor() {
+    use std::io::Read;
+    use std::process::Stdio;
+
+    let dir = fixture("substituted_ancestor");
+    fs::create_dir_all(dir.join("tree/sub/deep")).unwrap();
+    fs::create_dir_all(dir.join("secret/deep")).unwrap();
+    fs::write(dir.join("secret/deep/private"), "").unwrap();
+    // `sub`'s group overflows the pipe, so lsr is still writing it, before
+    // descending into `sub/deep`, when `sub` is swapped.
+    for index in 0..4000 {
+        let name = format!("{:060}", index);
+        fs::write(dir.join("tree/sub").join(name), "").unwrap();
+    }
+
+    let mut child = Command::cargo_bin(PROG_NAME)
+        .unwrap()
+        .current_dir(dir.join("tree"))
+        .args(["-R", "."])
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let mut stdout = child.stdout.take().unwrap();
+    let mut seen = Vec::new();
+    while !String::from_utf8_lossy(&seen).contains("./sub:\n") {
+        let mut byte = [0; 1];
+        stdout.read_exact(&mut byte).unwrap();
+        seen.push(byte[0]);
+    }
+
+    fs::rename(dir.join("tree/sub"), dir.join("moved")).unwrap();
+    std::os::unix::fs::symlink("../secret", dir.join("tree/sub")).unwrap();
+
+    let mut rest = String::new();
+    stdout.read_to_string(&mut rest).unwrap();
+    let output = child.wait_with_output().unwrap();
+    assert_eq!(output.status.code(), Some(1));
+    assert!(
+        !rest.contains("private"),
+        "followed the substituted ancestor"
+    );
+    assert_eq!(
+        String::from_utf8(output.stderr).unwrap(),
+        "lsr: cannot open directory './sub/deep': Not a directory\n"
+    );
+}
+
+#[cfg(unix)]
+#[test]
+fn test_sort_by_links() {
+    let dir = fixture("sort_by_links");
+    fs::write(dir.join("a_single"), "").unwrap();
+    fs::write(dir.join("b_linked"), "").unwrap();
+    fs::hard_link(dir.join("b_linked"), dir.join("c_alias")).unwrap();
+    fs::write(dir.join("d_single"), "").unwrap();
+
+    assert_eq!(
+        listing(&dir, &["--sort=links"]),
+        ["b_linked", "c_alias", "a_single", "d_single"]
+    );
+    assert_eq!(
+        listing(&dir, &["--sort=links", "-r"]),
+        ["d_single", "a_single", "c_alias", "b_linked"]
+    );
+}
+
+#[test]
+fn test_sort_by_child_count() {
+    let dir = fixture("sort_by_child_count");
+    for (sub, children) in [("a_one", 1), ("b_three", 3), ("c_empty", 0), ("d_two", 2)] {
+        fs::create_dir(dir.join(sub)).unwrap();
+        for index in 0..children {
+            fs::write(dir.join(sub).join(index.to_string()), "").unwrap();
+        }
+    }
+    fs::write(dir.join("e_file"), "").unwrap();
+
+    assert_eq!(
+        listing(&dir, &["-1", "--sort=child-count"]),
+        ["b_three", "d_two", "a_one", "c_empty", "e_file"]
+    );
+    assert_eq!(
+        listing(&dir, &["-1", "--sort=child-count", "-r"]),
+        ["e_file", "c_empty", "a_one", "d_two", "b_three"]
+    );
+
+    // With -R the groups follow the same order.
+    let headers: Vec<String> = listing(&dir, &["-1", "-R", "--sort=child-count"])
+        .into_iter()
+        .filter(|line| line.ends_with(':'))
+        .collect();
+    assert_eq!(headers.len(), 5);
+    assert!(headers[1].ends_with("b_three:") && headers[4].ends_with("c_empty:"));
+}
+
+#[test]
+fn test_debug_args_reports_resolution() {
+    let dir = fixture("debug_args");
+    fs::write(dir.join("a"), "x").unwrap();
+    fs::write(dir.join("b"), "xx").unwrap();
+
+    let mut cmd = Command::cargo_bin(PROG_NAME).unwrap();
+
+    // Set the command-line arguments and options
+    cmd.args(["--debug-args", "-S", "-r", "-1"]).arg(&dir);
+
+    // Run the command and check the output
+    cmd.assert()
+        .success()
+        .stdout("a\nb\n")
+        .stderr(predicate::str::contains("sort_field: Size"))
+        .stderr(predicate::str::contains("reverse: true"))
+        .stderr(predicate::str::contains("format: SingleColumn"))
+        .stderr(predicate::str::contains("lsr: stdout is a terminal: false"));
+}
+
+#[test]
+fn test_wrap_names() {
+    let dir = fixture("wrap_names");
+    fs::write(dir.join(format!("a{}", "x".repeat(20))), "").unwrap();
+    // "e" followed by combining acute accents, which must not be split off.
+    fs::write(dir.join(format!("b{}", "e\u{301}".repeat(12))), "").unwrap();
+    fs::write(dir.join("short"), "").unwrap();
+
+    assert_eq!(
+        listing(&dir, &["-1", "--wrap-names", "--width=10", "--number"]),
+        [
+            "1 axxxxxxx",
+            "  xxxxxxxx",
+            "  xxxxx",
+            &format!("2 b{}", "e\u{301}".repeat(7)),
+            &format!("  {}", "e\u{301}".repeat(5)),
+            "3 short"
+        ]
+    );
+    // Off by default, and a width of 0 never wraps.
+    assert_eq!(listing(&dir, &["-1", "--width=10"]).len(), 3);
+    assert_eq!(listing(&dir, &["-1", "--wrap-names", "--width=0"]).len(), 3);
+}
+
+#[test]
+fn test_paths_only() {
+    let dir = fixture("paths_only");
+    fs::create_dir_all(dir.join("sub/deeper")).unwrap();
+    fs::write(dir.join("b.log"), "").unwrap();
+    fs::write(dir.join("a.txt"), "").unwrap();
+    fs::write(dir.join("sub/c.txt"), "").unwrap();
+    fs::write(dir.join("sub/deeper/d.txt"), "").unwrap();
+
+    let mut cmd = Command::cargo_bin(PROG_NAME).unwrap();
+
+    // Set the command-line arguments and options
+    cmd.current_dir(&dir)
+        .args(["-R", "-F", "-s", "--color=always", "--paths-only", "."]);
+
+    // Run the command and check the output
+    cmd.assert()
+        .success()
+        .stdout("./a.txt\n./b.log\n./sub\n./sub/c.txt\n./sub/deeper\n./sub/deeper/d.txt\n");
+
+    let mut filtered = Command::cargo_bin(PROG_NAME).unwrap();
+    filtered.current_dir(&dir).args([
+        "-R",
+        "-r",
+        "--exclude=*.log",
+        "--paths-only",
+        "--zero",
+        "sub",
+    ]);
+    filtered
+        .assert()
+        .success()
+        .stdout("sub/deeper\0sub/c.txt\0sub/deeper/d.txt\0");
+}
+
+#[test]
+fn test_max_total_entries() {
+    let root = deep_tree("max_total_entries", 2, 3);
+
+    for flags in [&["-R"][..], &["-R", "--parallel"][..]] {
+        let mut cmd = Command::cargo_bin(PROG_NAME).unwrap();
+
+        // Set the command-line arguments and options
+        cmd.current_dir(&root)
+            .args(flags)
+            .args(["--max-total-entries=8", "."]);
+
+        // Run the command and check the output
+        cmd.assert()
+            .code(4)
+            .stdout(".:\ndir0\ndir1\ndir2\nfile0\nfile1\nfile2\n\n./dir0:\ndir0\ndir1\n")
+            .stderr("... (output truncated)\n");
+    }
+
+    // A cap the tree fits under changes nothing.
+    let mut roomy = Command::cargo_bin(PROG_NAME).unwrap();
+    roomy
+        .current_dir(&root)
+        .args(["-R", "--max-total-entries=1000", "."]);
+    roomy.assert().success().stderr("");
+}
+
+#[test]
+fn test_json_relative_times() {
+    let dir = fixture("json_relative");
+    let file = dir.join("old \"quoted\".log");
+    fs::write(&file, "abc").unwrap();
+    // 2020-01-01T00:00:00Z
+    set_mtime(&file, 1_577_836_800);
+
+    let mut plain = Command::cargo_bin(PROG_NAME).unwrap();
+
+    // Set the command-line arguments and options
+    plain.arg("--json").arg(&dir);
+
+    // Run the command and check the output
+    plain
+        .assert()
+        .success()
+        .stdout(predicate::str::starts_with(
+            "[\n{\"name\":\"old \\\"quoted\\\".log\"",
+        ))
+        .stdout(predicate::str::contains(
+            "\"type\":\"file\",\"size\":3,\"modified\":\"2020-01-01T00:00:00Z\"",
+        ))
+        .stdout(predicate::str::contains("_relative\"").not())
+        .stdout(predicate::str::ends_with("}\n]\n"));
+
+    let mut relative = Command::cargo_bin(PROG_NAME).unwrap();
+    relative
+        .args(["--json", "--json-relative", "--at=2020-01-04T00:00:00Z"])
+        .arg(&dir);
+    relative.assert().success().stdout(predicate::str::contains(
+        "\"modified\":\"2020-01-01T00:00:00Z\",\"modified_relative\":\"3 days ago\"",
+    ));
+}
+
+#[test]
+fn test_json_schema_version() {
+    let dir = fixture("json_schema");
+    fs::write(dir.join("file"), "").unwrap();
+
+    let mut listing = Command::cargo_bin(PROG_NAME).unwrap();
+
+    // Set the command-line arguments and options
+    listing.arg("--json").arg(&dir);
+
+    // Run the command and check the output
+    listing
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(",\"schema_version\":1}"));
+
+    let mut schema = Command::cargo_bin(PROG_NAME).unwrap();
+    schema.arg("--json-schema");
+    schema
+        .assert()
+        .success()
+        .stdout(predicate::str::starts_with("{\n"))
+        .stdout(predicate::str::contains(
+            "\"schema_version\": { \"const\": 1 }",
+        ))
+        .stdout(predicate::str::contains("\"additionalProperties\": false"));
+}
+
+#[test]
+fn test_prune_empty() {
+    let dir = fixture("prune_empty");
+    fs::create_dir_all(dir.join("big/deep")).unwrap();
+    fs::create_dir(dir.join("small")).unwrap();
+    fs::create_dir(dir.join("void")).unwrap();
+    fs::write(dir.join("big/deep/huge"), vec![b'x'; 2000]).unwrap();
+    fs::write(dir.join("small/tiny"), "x").unwrap();
+    fs::write(dir.join("top"), vec![b'x'; 5000]).unwrap();
+
+    let mut cmd = Command::cargo_bin(PROG_NAME).unwrap();
+
+    // Set the command-line arguments and options
+    cmd.current_dir(&dir)
+        .args(["-R", "--larger-than=1K", "--prune-empty", "."]);
+
+    // Run the command and check the output
+    cmd.assert()
+        .success()
+        .stdout(".:\nbig\ntop\n\n./big:\ndeep\n\n./big/deep:\nhuge\n");
+
+    let mut unfiltered = Command::cargo_bin(PROG_NAME).unwrap();
+    unfiltered
+        .current_dir(&dir)
+        .args(["-R", "--prune-empty", "."]);
+    unfiltered
+        .assert()
+        .success()
+        .stdout(".:\nbig\nsmall\ntop\n\n./big:\ndeep\n\n./big/deep:\nhuge\n\n./small:\ntiny\n");
+
+    // Pruning buffers the whole tree, which --low-memory rules out.
+    let mut low_memory = Command::cargo_bin(PROG_NAME).unwrap();
+    low_memory
+        .current_dir(&dir)
+        .args(["-R", "--prune-empty", "--low-memory", "."]);
+    low_memory
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("cannot be used with"));
+}
+
+#[test]
+fn test_fixed_column_widths() {
+    let dir = fixture("fixed_column_widths");
+    fs::write(dir.join("a_small"), "12345").unwrap();
+    fs::write(dir.join("b_large"), vec![b'x'; 123_456]).unwrap();
+
+    let cases: &[(&[&str], [&str; 2])] = &[
+        (&["--file-size"], ["     5 a_small", "123456 b_large"]),
+        (
+            &["--file-size", "--size-width=8"],
+            ["       5 a_small", "  123456 b_large"],
+        ),
+        (
+            &["--file-size", "--size-width=4"],
+            ["   5 a_small", "123456 b_large"],
+        ),
+        (
+            &["--file-size", "--size-width=4", "--truncate-columns"],
+            ["   5 a_small", "123… b_large"],
+        ),
+    ];
+
+    for (args, expected) in cases {
+        assert_eq!(listing(&dir, args), expected);
+    }
+}
+
+#[cfg(unix)]
+#[test]
+fn test_sort_ctime_uses_change_time() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let dir = fixture("sort_ctime");
+    fs::write(dir.join("a_chmod"), "").unwrap();
+    fs::write(dir.join("b_touched"), "").unwrap();
+    set_mtime(&dir.join("a_chmod"), 1_000_000);
+    set_mtime(&dir.join("b_touched"), 2_000_000);
+    // Changing the mode bumps a_chmod's ctime past b_touched's, while its
+    // mtime (and birth time) stay older.
+    fs::set_permissions(dir.join("a_chmod"), fs::Permissions::from_mode(0o600)).unwrap();
+
+    let lines = listing(&dir, &["--sort=time", "-c", "ctime"]);
+    assert!(lines[0].starts_with("a_chmod "), "{:?}", lines);
+    assert!(lines[1].starts_with("b_touched "), "{:?}", lines);
+    assert!(!lines[0].contains("1970"), "{:?}", lines);
+}
+
+#[cfg(unix)]
+#[test]
+fn test_audit_perms() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let dir = fixture("audit_perms");
+    let modes = [
+        ("plain", 0o644),
+        ("shared", 0o666),
+        ("su_tool", 0o4755),
+        ("sg_tool", 0o2755),
+    ];
+    for (name, mode) in modes {
+        fs::write(dir.join(name), "").unwrap();
+        fs::set_permissions(dir.join(name), fs::Permissions::from_mode(mode)).unwrap();
+    }
+
+    let mut cmd = Command::cargo_bin(PROG_NAME).unwrap();
+
+    // Set the command-line arguments and options
+    cmd.arg("--audit-perms").arg(&dir);
+
+    // Run the command and check the output
+    let shared = dir.join("shared");
+    let su_tool = dir.join("su_tool");
+    let sg_tool = dir.join("sg_tool");
+    cmd.assert().success().stdout(format!(
+        "world-writable (1):\n  -rw-rw-rw- {}\n\nsetuid (1):\n  -rwsr-xr-x {}\n\nsetgid (1):\n  -rwxr-sr-x {}\n",
+        shared.display(),
+        su_tool.display(),
+        sg_tool.display()
+    ));
+
+    // A world-writable setuid file fails the audit.
+    fs::set_permissions(&su_tool, fs::Permissions::from_mode(0o4777)).unwrap();
+    let mut cmd = Command::cargo_bin(PROG_NAME).unwrap();
+    cmd.arg("--audit-perms").arg(&dir);
+    cmd.assert()
+        .code(1)
+        .stderr(predicate::str::contains("world-writable setuid file"));
+}
+
+#[cfg(unix)]
+#[test]
+fn test_grid_column_fit_matches_coreutils() {
+    let dir = fixture("grid_column_fit");
+    // Widths vary a lot, so naive "widest name" division would waste space.
+    let lengths = [
+        1, 12, 3, 7, 2, 25, 4, 4, 9, 1, 16, 5, 3, 8, 2, 11, 6, 1, 19, 3,
+    ];
+    for (index, length) in lengths.iter().enumerate() {
+        let name = format!("{}{}", (b'a' + index as u8) as char, "x".repeat(length - 1));
+        fs::write(dir.join(name), "").unwrap();
+    }
+
+    for width in [20, 33, 40, 57, 64, 80, 101, 140] {
+        for flag in ["-C", "-x"] {
+            let expected = match Command::new("ls")
+                .env("LC_ALL", "C")
+                .args([flag, "-T0", "-w", &width.to_string()])
+                .arg(&dir)
+                .output()
+            {
+                Ok(output) if output.status.success() => output.stdout,
+                _ => return,
+            };
+            let expected = String::from_utf8(expected).unwrap();
+            assert_eq!(
+                listing(&dir, &[flag, "-w", &width.to_string()]),
+                expected.lines().collect::<Vec<_>>(),
+                "{} -w {}",
+                flag,
+                width
+            );
+        }
+    }
+}
+
+#[test]
+fn test_grid_orientation_matches_coreutils() {
+    let dir = fixture("grid_orientation");
+    let names = [
+        "alpha",
+        "b",
+        "charlie",
+        "delta_long_name",
+        "e",
+        "foxtrot",
+        "golf",
+        "hotel_x",
+        "india",
+        "j",
+        "kilo",
+        "lima_lima_lima",
+        "mike",
+    ];
+    for name in names {
+        fs::write(dir.join(name), "").unwrap();
+    }
+
+    // Captured from GNU coreutils 9.1 `ls -w 40 -T0` on the same 13 names.
+    let vertical = [
+        "alpha            hotel_x",
+        "b                india",
+        "charlie          j",
+        "delta_long_name  kilo",
+        "e                lima_lima_lima",
+        "foxtrot          mike",
+        "golf",
+    ];
+    let across = [
+        "alpha    b",
+        "charlie  delta_long_name",
+        "e        foxtrot",
+        "golf     hotel_x",
+        "india    j",
+        "kilo     lima_lima_lima",
+        "mike",
+    ];
+    assert_eq!(listing(&dir, &["-C", "-w", "40"]), vertical);
+    assert_eq!(listing(&dir, &["--format=vertical", "-w", "40"]), vertical);
+    assert_eq!(listing(&dir, &["-x", "-w", "40"]), across);
+    assert_eq!(listing(&dir, &["--format=across", "-w", "40"]), across);
+    // The later layout flag wins.
+    assert_eq!(listing(&dir, &["-x", "-C", "-w", "40"]), vertical);
+
+    // `ls -w 80 -T0`: everything fits on two rows.
+    assert_eq!(
+        listing(&dir, &["-C", "-w", "80"]),
+        [
+            "alpha  charlie          e        golf     india  kilo            mike",
+            "b      delta_long_name  foxtrot  hotel_x  j      lima_lima_lima",
+        ]
+    );
+
+    let mut cmd = Command::cargo_bin(PROG_NAME).unwrap();
+
+    // Set the command-line arguments and options
+    cmd.env("LSR_FORCE_TTY", "1").arg("-w").arg("40").arg(&dir);
+
+    // Run the command and check the output
+    cmd.assert().success().stdout(vertical.join("\n") + "\n");
+}
+
+#[test]
+fn test_number_entries() {
+    let dir = fixture("number_entries");
+    for index in 0..10 {
+        fs::write(dir.join(format!("f{}", index)), "").unwrap();
+    }
+    fs::create_dir(dir.join("sub")).unwrap();
+    fs::write(dir.join("sub").join("x"), "").unwrap();
+
+    let lines = listing(&dir, &["--number"]);
+    assert_eq!(lines[0], " 1 f0");
+    assert_eq!(lines[9], "10 f9");
+    assert_eq!(lines[10], "11 sub");
+
+    let lines = listing(&dir, &["-R", "--number"]);
+    assert_eq!(lines.last().unwrap(), "12 x");
+
+    let lines = listing(&dir, &["-R", "--number=per-dir"]);
+    assert_eq!(lines.last().unwrap(), "1 x");
+}
+
+#[test]
+fn test_exclude_glob() {
+    let dir = fixture("exclude_glob");
+    for name in ["app.log", "app.rs", "data1", "data2", "notes.txt"] {
+        fs::write(dir.join(name), "").unwrap();
+    }
+    fs::create_dir(dir.join("target")).unwrap();
+    fs::write(dir.join("target").join("inner.rs"), "").unwrap();
+
+    assert_eq!(
+        listing(&dir, &["--exclude", "*.log", "--exclude=data[!2]"]),
+        ["app.rs", "data2", "notes.txt", "target"]
+    );
+    // Excluded directories are not descended into.
+    assert!(!listing(&dir, &["-R", "--exclude=target"]).contains(&"inner.rs".to_string()));
+}
+
+#[cfg(unix)]
+#[test]
+fn test_exclude_owner() {
+    use std::os::unix::fs::MetadataExt;
+
+    let dir = fixture("exclude_owner");
+    fs::write(dir.join("mine"), "").unwrap();
+    fs::write(dir.join("theirs"), "").unwrap();
+    let uid = fs::metadata(dir.join("mine")).unwrap().uid().to_string();
+    // Handing a file to another user needs root; otherwise both are ours.
+    let chowned = std::os::unix::fs::chown(dir.join("theirs"), Some(65534), None).is_ok();
+
+    let expected: &[&str] = if chowned { &["theirs"] } else { &[] };
+    assert_eq!(listing(&dir, &["--exclude-owner", &uid]), expected);
+
+    let mut cmd = Command::cargo_bin(PROG_NAME).unwrap();
+
+    // Set the command-line arguments and options
+    cmd.arg("--exclude-owner=no-such-user-lsr").arg(&dir);
+
+    // Run the command and check the output
+    cmd.assert()
+        .failure()
+        .stderr(predicate::str::contains("invalid user 'no-such-user-lsr'"));
+}
+
+#[cfg(unix)]
+#[test]
+fn test_mark_hardlinks() {
+    let dir = fixture("mark_hardlinks");
+    fs::write(dir.join("a_original"), "x").unwrap();
+    fs::hard_link(dir.join("a_original"), dir.join("b_link")).unwrap();
+    fs::write(dir.join("c_other"), "y").unwrap();
+    fs::hard_link(dir.join("c_other"), dir.join("d_other_link")).unwrap();
+    fs::write(dir.join("e_single"), "z").unwrap();
+
+    assert_eq!(
+        listing(&dir, &["--mark-hardlinks"]),
+        [
+            "a_original [#1]",
+            "b_link [#1]",
+            "c_other [#2]",
+            "d_other_link [#2]",
+            "e_single",
+        ]
+    );
+}
+
+#[test]
+fn test_align_extensions() {
+    let dir = fixture("align_ext");
+    for name in [
+        ".bashrc",
+        "Makefile",
+        "image.png",
+        "report.pdf",
+        "v1.tar.gz",
+    ] {
+        fs::write(dir.join(name), "").unwrap();
+    }
+
+    assert_eq!(
+        listing(&dir, &["-a", "--align-ext"]),
+        [
+            ".bashrc",
+            "Makefile",
+            "image .png",
+            "report.pdf",
+            "v1.tar.gz",
+        ]
+    );
+}
+
+#[test]
+fn test_fail_on_empty() {
+    let dir = fixture("fail_on_empty");
+
+    let mut cmd = Command::cargo_bin(PROG_NAME).unwrap();
+
+    // Set the command-line arguments and options
+    cmd.arg("--fail-on-empty").arg(&dir);
+
+    // Run the command and check the output
+    cmd.assert().code(3).stdout("");
+
+    // Filters count: a match passes, a miss fails.
+    fs::write(dir.join("notes.txt"), "").unwrap();
+    let mut cmd = Command::cargo_bin(PROG_NAME).unwrap();
+    cmd.arg("--fail-on-empty").arg(&dir);
+    cmd.assert().success().stdout("notes.txt\n");
+
+    let mut cmd = Command::cargo_bin(PROG_NAME).unwrap();
+    cmd.arg("--fail-on-empty").arg("--exclude=*.txt").arg(&dir);
+    cmd.assert().code(3);
+
+    // Other output modes count what they would report, too.
+    let mut json = Command::cargo_bin(PROG_NAME).unwrap();
+    json.args(["--json", "--fail-on-empty"]).arg(&dir);
+    json.assert().success();
+
+    let mut json = Command::cargo_bin(PROG_NAME).unwrap();
+    json.args(["--json", "--fail-on-empty", "--exclude=*.txt"])
+        .arg(&dir);
+    json.assert().code(3);
+
+    let mut exec = Command::cargo_bin(PROG_NAME).unwrap();
+    exec.args(["--exec=true", "--fail-on-empty", "--exclude=*.txt"])
+        .arg(&dir);
+    exec.assert().code(3);
+}
+
+#[test]
+fn test_lsrignore() {
+    let dir = fixture("lsrignore");
+    fs::write(
+        dir.join(".lsrignore"),
+        "# build output\n*.o\n\nsecret.txt\n",
+    )
+    .unwrap();
+    fs::write(dir.join("main.c"), "").unwrap();
+    fs::write(dir.join("main.o"), "").unwrap();
+    fs::write(dir.join("secret.txt"), "").unwrap();
+    fs::create_dir(dir.join("sub")).unwrap();
+    fs::write(dir.join("sub").join("util.o"), "").unwrap();
+    fs::write(dir.join("sub").join("util.c"), "").unwrap();
+
+    assert_eq!(listing(&dir, &[]), ["main.c", "sub"]);
+    // Patterns also cover subdirectories, but only within the listing.
+    let recursive = listing(&dir, &["-R"]);
+    let sub = recursive
+        .iter()
+        .position(|line| line.ends_with("sub:"))
+        .unwrap();
+    assert_eq!(recursive[sub + 1..], ["util.c"]);
+    assert_eq!(listing(&dir.join("sub"), &[]), ["util.c", "util.o"]);
+    assert_eq!(
+        listing(&dir, &["--no-ignore"]),
+        ["main.c", "main.o", "secret.txt", "sub"]
+    );
+}
+
+#[test]
+fn test_ignore_file_precedence() {
+    let dir = fixture("ignore_precedence");
+    fs::create_dir(dir.join("sub")).unwrap();
+    for name in ["a.log", "b.log", "c.log", "keep.tmp", "drop.tmp"] {
+        fs::write(dir.join("sub").join(name), "").unwrap();
+    }
+    // Farther directory: hide every log and temp file.
+    fs::write(dir.join(".ignore"), "*.log\n*.tmp\n").unwrap();
+    // Nearer directory: .ignore re-includes the logs, .rgignore hides b.log
+    // again, and .lsrignore, which wins over both, brings back c.log.
+    fs::write(dir.join("sub/.ignore"), "!*.log\n").unwrap();
+    fs::write(dir.join("sub/.rgignore"), "b.log\nc.log\n!keep.tmp\n").unwrap();
+    fs::write(dir.join("sub/.lsrignore"), "!c.log\n").unwrap();
+
+    let recursive = listing(&dir, &["-R"]);
+    let sub = recursive
+        .iter()
+        .position(|line| line.ends_with("sub:"))
+        .unwrap();
+    assert_eq!(recursive[sub + 1..], ["a.log", "c.log", "keep.tmp"]);
+    let recursive = listing(&dir, &["-R", "--no-ignore"]);
+    let sub = recursive
+        .iter()
+        .position(|line| line.ends_with("sub:"))
+        .unwrap();
+    assert_eq!(
+        recursive[sub + 1..],
+        ["a.log", "b.log", "c.log", "drop.tmp", "keep.tmp"]
+    );
+}
+
+#[cfg(unix)]
+#[test]
+fn test_classify_survives_color_never() {
+    let dir = fixture("classify_color_never");
+    fs::create_dir(dir.join("sub")).unwrap();
+    fs::write(dir.join("plain"), "").unwrap();
+    std::os::unix::fs::symlink("plain", dir.join("link")).unwrap();
+
+    // Indicators are structure, not color: turning color off keeps them,
+    // whether or not stdout is a terminal.
+    for tty in ["0", "1"] {
+        let mut cmd = Command::cargo_bin(PROG_NAME).unwrap();
+
+        // Set the command-line arguments and options
+        cmd.env("LSR_FORCE_TTY", tty)
+            .args(["-1", "-F", "--color=never"])
+            .arg(&dir);
+
+        // Run the command and check the output
+        cmd.assert().success().stdout("link@\nplain\nsub/\n");
+    }
+}
+
+#[test]
+fn test_split_output() {
+    let dir = fixture("split_output");
+    let tree = dir.join("tree");
+    fs::create_dir_all(tree.join("docs").join("_listing.txt")).unwrap();
+    fs::write(tree.join("readme"), "").unwrap();
+    fs::write(tree.join("docs").join("guide"), "").unwrap();
+    let out = dir.join("snapshot");
+
+    let mut cmd = Command::cargo_bin(PROG_NAME).unwrap();
+
+    // Set the command-line arguments and options
+    cmd.arg("-R").arg("--split-output").arg(&out).arg(&tree);
+
+    // Run the command and check the output
+    cmd.assert().success().stdout("");
+    let read = |path: PathBuf| fs::read_to_string(path).unwrap();
+    assert_eq!(read(out.join("_listing.txt")), "docs\nreadme\n");
+    // A subdirectory named like the listing file pushes it aside.
+    assert_eq!(
+        read(out.join("docs").join("_listing.txt.1")),
+        "_listing.txt\nguide\n"
+    );
+    assert_eq!(read(out.join("docs/_listing.txt/_listing.txt")), "");
+
+    let mut cmd = Command::cargo_bin(PROG_NAME).unwrap();
+    cmd.arg("-R")
+        .arg("--split-output")
+        .arg(tree.join("snapshot"))
+        .arg(&tree);
+    cmd.assert()
+        .failure()
+        .stderr(predicate::str::contains("is inside the listed tree"));
+}
+
+#[cfg(unix)]
+#[test]
+fn test_indicator_styles() {
+    let dir = fixture("indicator_styles");
+    fs::create_dir(dir.join("sub")).unwrap();
+    fs::write(dir.join("plain"), "").unwrap();
+    std::os::unix::fs::symlink("plain", dir.join("link")).unwrap();
+
+    let slash = ["link", "plain", "sub/"];
+    let classify = ["link@", "plain", "sub/"];
+    assert_eq!(listing(&dir, &["-p"]), slash);
+    assert_eq!(listing(&dir, &["--indicator-style=slash"]), slash);
+    assert_eq!(listing(&dir, &["-F"]), classify);
+    assert_eq!(listing(&dir, &["--indicator-style=classify"]), classify);
+    // The style with more indicators wins, whatever the order.
+    assert_eq!(listing(&dir, &["-p", "-F"]), classify);
+    assert_eq!(listing(&dir, &["-F", "-p"]), classify);
+    assert_eq!(listing(&dir, &["-p", "--indicator-style=none"]), slash);
+    assert_eq!(
+        listing(&dir, &["--indicator-style=none"]),
+        ["link", "plain", "sub"]
+    );
+}
+
+#[test]
+fn test_git_modified() {
+    let dir = fixture("git_modified");
+    let git = |args: &[&str]| {
+        Command::new("git")
+            .current_dir(&dir)
+            .args(["-c", "user.name=lsr", "-c", "user.email=lsr@example.com"])
+            .args(args)
+            .status()
+            .is_ok_and(|status| status.success())
+    };
+    if !git(&["init", "-q"]) {
+        // No git available.
+        return;
+    }
+    fs::create_dir(dir.join("src")).unwrap();
+    for name in ["changed", "clean", "src/lib.rs", "staged"] {
+        fs::write(dir.join(name), "").unwrap();
+    }
+    assert!(git(&["add", "-A"]));
+    assert!(git(&["commit", "-q", "-m", "initial"]));
+    fs::write(dir.join("changed"), "edit").unwrap();
+    fs::write(dir.join("staged"), "edit").unwrap();
+    assert!(git(&["add", "staged"]));
+    fs::write(dir.join("untracked"), "").unwrap();
+
+    assert_eq!(
+        listing(&dir, &["--git-modified"]),
+        ["changed", "staged", "untracked"]
+    );
+
+    let mut cmd = Command::cargo_bin(PROG_NAME).unwrap();
+
+    // Set the command-line arguments and options
+    cmd.arg("--git-modified")
+        .arg(std::env::temp_dir().join("lsr-no-such-repo"));
+
+    // Run the command and check the output
+    cmd.assert()
+        .failure()
+        .stderr(predicate::str::contains("--git-modified"));
+}
+
+#[cfg(unix)]
+#[test]
+fn test_unescape_round_trip() {
+    use std::ffi::OsStr;
+    use std::os::unix::ffi::OsStrExt;
+
+    let dir = fixture("unescape_round_trip");
+    let names: [&[u8]; 5] = [
+        b"back\\slash",
+        b"bell\x07",
+        b"latin1\xe9",
+        b"line\nbreak",
+        b"tab\there",
+    ];
+    for name in names {
+        fs::write(dir.join(OsStr::from_bytes(name)), "").unwrap();
+    }
+    let mut expected: Vec<u8> = Vec::new();
+    for name in names {
+        expected.extend(name);
+        expected.push(b'\n');
+    }
+
+    let input = fixture("unescape_round_trip_input").join("escaped");
+    for style in ["octal", "hex"] {
+        let escaped = Command::cargo_bin(PROG_NAME)
+            .unwrap()
+            .arg(format!("--escape-style={}", style))
+            .arg(&dir)
+            .output()
+            .unwrap()
+            .stdout;
+        fs::write(&input, escaped).unwrap();
+
+        let mut cmd = Command::cargo_bin(PROG_NAME).unwrap();
+
+        // Set the command-line arguments and options
+        cmd.arg("--unescape").stdin(fs::File::open(&input).unwrap());
+
+        // Run the command and check the output
+        cmd.assert().success().stdout(expected.clone());
+    }
+
+    fs::write(&input, "fine\\040name\nbad\\q\n").unwrap();
+    let mut cmd = Command::cargo_bin(PROG_NAME).unwrap();
+    cmd.arg("--unescape").stdin(fs::File::open(&input).unwrap());
+    cmd.assert().failure().stderr(predicate::str::contains(
+        "record 2: invalid escape in 'bad\\\\q'",
+    ));
+}